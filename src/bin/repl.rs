@@ -1,20 +1,22 @@
 use eden::kurt::{expr::_dict, Kurt};
 use rustyline::error::ReadlineError;
-use velcro::hash_map;
+use velcro::map_iter;
 
 fn main() {
     let kurt = Kurt::new();
-    let env = _dict(hash_map!(
+    let env = _dict(map_iter!(
         "^".into(): kurt.root.clone(),
-    ));
+    ).collect());
 
     let mut rl = rustyline::Editor::<()>::new();
     loop {
         match rl.readline("kurt > ") {
             Ok(line) => {
                 if line.trim().len() > 0 {
-                    let result = kurt.eval_src(&env, "repl", line.as_str());
-                    println!("{}", result);
+                    match kurt.run(&env, "repl", line.as_str()) {
+                        Ok(result) => println!("{:#}", result),
+                        Err(err) => println!("{}", err),
+                    }
                 }
             }
 
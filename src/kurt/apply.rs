@@ -1,10 +1,9 @@
-use std::{borrow::Borrow, collections::HashMap};
-
 use crate::kurt::expr::{Dict, ERef, Exprs, _dict, _list};
 
 use super::{
-    expr::{Block, Expr, _NIL},
-    Kurt,
+    expr::{Block, Expr, Key, Param, Pattern, _NIL},
+    ordered_map::OrderedMap,
+    Exception, Kurt,
 };
 
 impl Kurt {
@@ -16,7 +15,43 @@ impl Kurt {
     // - Apply single expression -- (expr) => expr
     // - Apply empty list        -- () => nil
     //
-    pub fn apply(&self, env: &Expr, exprs: Vec<Expr>) -> Expr {
+    pub fn apply(&self, env: &Expr, exprs: Vec<Expr>) -> Result<Expr, Exception> {
+        // Calls in tail position (block bodies, (env expr), and natives' apply_tail()) continue
+        // this loop rather than recursing, so that they run in constant Rust stack.
+        let mut env = env.clone();
+        let mut exprs = exprs;
+        let caller = env.clone();
+        let mut wrap: Option<Expr> = None;
+        loop {
+            let step = match self.apply_step(&caller, &env, exprs) {
+                // Returns to any frame created by this loop complete it.
+                Err(Exception::Return(frame, value)) if self.is_frame_of(&frame, &caller) => {
+                    Step::Done(value)
+                }
+                result => result?,
+            };
+            match step {
+                Step::Done(result) => {
+                    return Ok(match wrap {
+                        Some(slf) => self.maybe_wrap(slf, result),
+                        None => result,
+                    })
+                }
+                Step::Tail(next_env, next_exprs, slf) => {
+                    // Only the outermost wrap matters, as it would have replaced any inner ones.
+                    if wrap.is_none() {
+                        wrap = slf;
+                    }
+                    env = next_env;
+                    exprs = next_exprs;
+                }
+            }
+        }
+    }
+
+    // Takes a single step of apply(), either producing a result or the next apply in tail position.
+    // Frames created by tail applies replace their callers, so they all share the original caller.
+    fn apply_step(&self, caller: &Expr, env: &Expr, exprs: Vec<Expr>) -> Result<Step, Exception> {
         if self.debug {
             let ls = _list(exprs.clone());
             // println!("apply -- {} :: {}", env.clone(), ls);
@@ -25,44 +60,83 @@ impl Kurt {
 
         // () => nil
         if exprs.len() == 0 {
-            return _NIL;
+            return Ok(Step::Done(_NIL));
         }
 
-        let first = &self.eval(env, exprs.first().unwrap());
+        let first = &self.eval(env, exprs.first().unwrap())?;
         match first {
+            // (macro expr*) -> expand with the unevaluated args, then evaluate the expansion.
+            Expr::EBlock(block_ref) if block_ref.borrow().is_macro => {
+                let expansion = self.call_block(env, first, exprs[1..].to_vec())?;
+                self.eval_tail(env, &expansion)
+            }
+
             // (block expr*) -> positional arg invocation
-            Expr::EBlock(_) => self.invoke(env, first.clone(), exprs[1..].to_vec()),
-
-            _ => {
-                let result = match exprs.len() {
-                    // (expr) -> expr
-                    1 => first.clone(),
-
-                    2 => {
-                        let second = &self.eval(env, exprs.get(1).unwrap());
-                        match second {
-                            // (env block) -> eval block in env.
-                            Expr::EBlock(block_ref) => {
-                                let block = &*block_ref.borrow();
-                                let frame = self.new_frame(&env, &first, &block);
-                                self.eval(&frame, &block.expr)
-                            }
+            Expr::EBlock(_) => {
+                let frame = self.invoke(env, first, &exprs[1..])?;
+                Ok(Step::Tail(env.clone(), vec![frame, first.clone()], None))
+            }
+
+            _ => match exprs.len() {
+                // (expr) -> expr
+                1 => Ok(Step::Done(first.clone())),
 
-                            // (env expr) -> eval expr in env
-                            _ => self.eval(first, second),
+                2 => {
+                    let second = &self.eval(env, exprs.get(1).unwrap())?;
+                    let step = match second {
+                        // (env block) -> eval block in env.
+                        Expr::EBlock(block_ref) => {
+                            let block = &*block_ref.borrow();
+                            let frame = self.new_frame(env, caller, first, block);
+                            self.eval_tail(&frame, &block.expr)?
                         }
-                    }
 
-                    _ => self.throw(
-                        env,
-                        format!("apply allows no more than 2 arguments: {}", Exprs(exprs)),
-                    ),
-                };
-                self.maybe_wrap(first.clone(), result)
+                        // (env expr) -> eval expr in env
+                        _ => self.eval_tail(first, second)?,
+                    };
+                    Ok(match step {
+                        Step::Done(result) => Step::Done(self.maybe_wrap(first.clone(), result)),
+                        Step::Tail(next_env, next_exprs, _) => {
+                            Step::Tail(next_env, next_exprs, Some(first.clone()))
+                        }
+                    })
+                }
+
+                _ => self.throw(
+                    env,
+                    format!("apply allows no more than 2 arguments: {}", Exprs(exprs)),
+                ),
+            },
+        }
+    }
+
+    // Evaluates an expr in tail position, deferring applies (and natives' tail applies) to the caller.
+    fn eval_tail(&self, env: &Expr, expr: &Expr) -> Result<Step, Exception> {
+        match expr {
+            Expr::EApply(apply_ref) => Ok(Step::Tail(
+                env.clone(),
+                apply_ref.borrow().exprs.clone(),
+                None,
+            )),
+            Expr::ENative(_) => {
+                let result = self.eval_native(env, expr)?;
+                let tail = self.tail.borrow_mut().take();
+                match tail {
+                    Some((next_env, next_exprs)) => Ok(Step::Tail(next_env, next_exprs, None)),
+                    None => Ok(Step::Done(result)),
+                }
             }
+            _ => Ok(Step::Done(self.eval(env, expr)?)),
         }
     }
 
+    // Used by natives to apply exprs in tail position, as their final step.
+    // The apply is deferred until the native returns (its return value is ignored).
+    pub fn apply_tail(&self, env: &Expr, exprs: Vec<Expr>) -> Result<Expr, Exception> {
+        *self.tail.borrow_mut() = Some((env.clone(), exprs));
+        Ok(_NIL)
+    }
+
     fn maybe_wrap(&self, slf: Expr, result: Expr) -> Expr {
         match &result {
             Expr::EBlock(blk_ref) => {
@@ -74,40 +148,114 @@ impl Kurt {
                     env: block.env.clone(),
                     expr: block.expr.clone(),
                     slf: slf.clone(),
+                    is_macro: block.is_macro,
                 }))
             }
             _ => result,
         }
     }
 
-    fn invoke(&self, env: &Expr, block_expr: Expr, args: Vec<Expr>) -> Expr {
+    // Evaluates args and binds them to a block's params, returning the new frame's locals.
+    // Spread args (arg...) pass a list's items as positional args, or a dict's entries as keyword
+    // args.
+    fn invoke(&self, env: &Expr, block_expr: &Expr, args: &[Expr]) -> Result<Expr, Exception> {
         if self.debug {
-            let ls = _list(args.clone());
+            let ls = _list(args.to_vec());
             // println!("invoke -- {} :: {}", env.clone(), ls);
             println!("invoke :: {}", ls);
         }
 
-        if let Expr::EBlock(block_ref) = block_expr.borrow() {
+        let mut vals = Vec::<Expr>::new();
+        let mut kwargs = OrderedMap::<String, Expr>::new();
+        for arg in args {
+            match arg {
+                Expr::ESpread(spread_ref) => match &self.eval(env, &spread_ref.borrow())? {
+                    Expr::EList(list_ref) => vals.extend(list_ref.borrow().exprs.iter().cloned()),
+                    Expr::EDict(dict_ref) => {
+                        for (key, value) in &dict_ref.borrow().map {
+                            match key.as_id() {
+                                Some("^") => (),
+                                Some(name) => {
+                                    kwargs.insert(name.to_string(), value.clone());
+                                }
+                                None => return self.throw(env, format!("keyword args require id keys, got {}", key)),
+                            }
+                        }
+                    }
+                    other => {
+                        return self.throw(env, format!("spread requires a list or dict, got {:#}", other))
+                    }
+                },
+                _ => vals.push(self.eval(env, arg)?),
+            }
+        }
+        self.bind_args(env, block_expr, vals, kwargs)
+    }
+
+    // Invokes a block with already-evaluated args, binding them to its params.
+    pub fn call_block(&self, env: &Expr, block_expr: &Expr, args: Vec<Expr>) -> Result<Expr, Exception> {
+        let frame = self.bind_args(env, block_expr, args, OrderedMap::new())?;
+        self.apply(env, vec![frame, block_expr.clone()])
+    }
+
+    // As call_block(), but with keyword args, and in tail position (see apply_tail()).
+    pub fn call_block_tail(
+        &self,
+        env: &Expr,
+        block_expr: &Expr,
+        args: Vec<Expr>,
+        kwargs: OrderedMap<String, Expr>,
+    ) -> Result<Expr, Exception> {
+        let frame = self.bind_args(env, block_expr, args, kwargs)?;
+        self.apply_tail(env, vec![frame, block_expr.clone()])
+    }
+
+    // Binds positional and keyword args to a block's params, validating them.
+    // Params that aren't passed take their default, evaluated in the block's env along with the
+    // params before them. A trailing rest param (e.g. rest...) takes any remaining positional args.
+    fn bind_args(
+        &self,
+        env: &Expr,
+        block_expr: &Expr,
+        args: Vec<Expr>,
+        mut kwargs: OrderedMap<String, Expr>,
+    ) -> Result<Expr, Exception> {
+        if let Expr::EBlock(block_ref) = block_expr {
             let block = &*block_ref.borrow();
-            let mut frame = HashMap::<String, Expr>::new();
-            for i in 0..args.len() {
-                let param = &block.params[i];
-                if param.ends_with("...") {
-                    // Handle rest params.
-                    let mut rest = Vec::<Expr>::new();
-                    for arg in &args[i..] {
-                        rest.push(self.eval(env, arg));
+            let (params, rest) = split_rest(&block.params);
+            if rest.is_none() && args.len() > params.len() {
+                return self.throw(env, self.arity_message(block, args.len() + kwargs.len()));
+            }
+
+            let mut frame = OrderedMap::<Key, Expr>::new();
+            for (i, param) in params.iter().enumerate() {
+                // Only plain (non-destructuring) params can be passed by keyword.
+                let keyword = match &param.pattern {
+                    Pattern::Id(name) => kwargs.remove(name),
+                    _ => None,
+                };
+                let value = match (args.get(i), keyword) {
+                    (Some(_), Some(_)) => {
+                        return self.throw(
+                            env,
+                            format!("{} got multiple values for '{}'", block_name(block), param.pattern),
+                        )
                     }
-                    frame.insert(block.params[i].clone(), _list(rest));
-                    break;
-                } else {
-                    frame.insert(block.params[i].clone(), self.eval(env, &args[i]));
+                    (Some(arg), None) => Some(arg.clone()),
+                    (None, keyword) => keyword,
+                };
+                if !self.bind_param(env, block, param, value, &mut frame)? {
+                    return self.throw(env, self.arity_message(block, args.len() + kwargs.len()));
                 }
             }
-
-            // TODO: validate param/arg match.
-            let nf = _dict(frame);
-            self.apply(env, vec![nf.clone(), block_expr.clone()])
+            if let Some(name) = kwargs.keys().next() {
+                return self.throw(env, format!("{} has no param '{}'", block_name(block), name));
+            }
+            if let Some(rest) = rest {
+                let extra = args.get(params.len()..).unwrap_or(&[]).to_vec();
+                frame.insert(rest.as_str().into(), _list(extra));
+            }
+            Ok(_dict(frame))
         } else {
             self.throw(
                 env,
@@ -116,26 +264,195 @@ impl Kurt {
         }
     }
 
-    fn new_frame(&self, env: &Expr, args: &Expr, blk: &Block) -> Expr {
-        let mut new_map = HashMap::<String, Expr>::new();
+    // Binds a value to a param, or its default if there's no value.
+    // Returns false if there's neither, leaving the caller to report what's missing.
+    fn bind_param(
+        &self,
+        env: &Expr,
+        block: &Block,
+        param: &Param,
+        value: Option<Expr>,
+        frame: &mut OrderedMap<Key, Expr>,
+    ) -> Result<bool, Exception> {
+        let value = match (value, &param.default) {
+            (Some(value), _) => value,
+            (None, Some(default)) => {
+                // Defaults see the locals bound so far, in a scope under the block's env.
+                let mut scope = frame.clone();
+                let parent = match &block.env {
+                    Expr::ENil => self.root.clone(),
+                    parent => parent.clone(),
+                };
+                scope.insert("^".into(), parent);
+                self.eval(&_dict(scope), default)?
+            }
+            (None, None) => return Ok(false),
+        };
+        self.bind_pattern(env, block, &param.pattern, value, frame)?;
+        Ok(true)
+    }
+
+    // Destructures a value into frame's locals, according to a pattern.
+    fn bind_pattern(
+        &self,
+        env: &Expr,
+        block: &Block,
+        pattern: &Pattern,
+        value: Expr,
+        frame: &mut OrderedMap<Key, Expr>,
+    ) -> Result<(), Exception> {
+        match pattern {
+            Pattern::Id(name) => {
+                frame.insert(name.as_str().into(), value);
+                Ok(())
+            }
+
+            Pattern::Rest(_) => self.throw(
+                env,
+                format!("{}: rest param '{}' must be last", block_name(block), pattern),
+            ),
+
+            // Extra items are ignored, so [x y] can take the start of a longer list.
+            Pattern::List(params) => {
+                let items = match &value {
+                    Expr::EList(list_ref) => list_ref.borrow().exprs.clone(),
+                    _ => {
+                        return self.throw(
+                            env,
+                            format!("{}: expected a list for {}, got {:#}", block_name(block), pattern, value),
+                        )
+                    }
+                };
+                let (params, rest) = split_rest(params);
+                for (i, param) in params.iter().enumerate() {
+                    if !self.bind_param(env, block, param, items.get(i).cloned(), frame)? {
+                        return self.throw(
+                            env,
+                            format!("{}: missing item {} for {} in {:#}", block_name(block), i, pattern, value),
+                        );
+                    }
+                }
+                if let Some(rest) = rest {
+                    let extra = items.get(params.len()..).unwrap_or(&[]).to_vec();
+                    frame.insert(rest.as_str().into(), _list(extra));
+                }
+                Ok(())
+            }
+
+            // Keys are looked up in the dict and its parents, but not the dict methods, so that
+            // e.g. {:len:0} takes its default rather than binding the len method.
+            Pattern::Dict(entries) => {
+                if !matches!(value, Expr::EDict(_)) {
+                    return self.throw(
+                        env,
+                        format!("{}: expected a dict for {}, got {:#}", block_name(block), pattern, value),
+                    );
+                }
+                for (key, param) in entries {
+                    let item = self.dict_entry(&value, key.as_str());
+                    if !self.bind_param(env, block, param, item, frame)? {
+                        return self.throw(
+                            env,
+                            format!("{}: missing key '{}' for {} in {:#}", block_name(block), key, pattern, value),
+                        );
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // E.g. "'move' expects 2 args (x y), got 3", or "'range' expects 1 to 3 args (start end:nil
+    // step:1), got 0".
+    fn arity_message(&self, block: &Block, got: usize) -> String {
+        let required = block.params.iter().filter(|p| p.default.is_none() && !p.is_rest()).count();
+        let positional = block.params.iter().filter(|p| !p.is_rest()).count();
+        let expected = if block.params.iter().any(|p| p.is_rest()) {
+            format!("at least {}", required)
+        } else if required < positional {
+            format!("{} to {}", required, positional)
+        } else {
+            required.to_string()
+        };
+        let params = block.params.iter().map(|p| p.to_string()).collect::<Vec<String>>();
+        format!(
+            "{} expects {} arg{} ({}), got {}",
+            block_name(block),
+            expected,
+            if positional == 1 && required == 1 { "" } else { "s" },
+            params.join(" "),
+            got
+        )
+    }
+
+    // Creates a frame for evaluating a block in env, with args as its locals.
+    // Frames don't reference env, other than through the caller (for stack traces), so that frames
+    // replaced by tail calls can be collected:
+    // - Natives' @ is the env they're invoked in (e.g. for def and set).
+    // - Other blocks' @ is the @ of the scope they were defined in.
+    // If a block's @ were env instead, each frame in a tail-call loop would hold on to the one
+    // before it, and the chain would grow with the loop (overflowing the stack when collected).
+    fn new_frame(&self, env: &Expr, caller: &Expr, args: &Expr, blk: &Block) -> Expr {
+        let mut new_map = OrderedMap::<Key, Expr>::new();
         if let Expr::EDict(map_ref) = args {
             for (key, expr) in &map_ref.borrow().map {
                 new_map.insert(key.clone(), expr.clone());
             }
         }
-        new_map.insert("caller".to_string(), env.clone());
+        new_map.insert("caller".into(), caller.clone());
         new_map.insert(
-            "@".to_string(),
-            if blk.slf != Expr::ENil {
-                blk.slf.clone()
-            } else {
-                env.clone()
+            "@".into(),
+            match (&blk.slf, &blk.expr) {
+                (Expr::ENil, Expr::ENative(_)) => env.clone(),
+                (Expr::ENil, _) => self.lexical_self(&blk.env),
+                (slf, _) => slf.clone(),
             },
         );
-        new_map.insert("^".to_string(), blk.env.clone());
+        new_map.insert("^".into(), blk.env.clone());
         Expr::EDict(ERef::new(Dict {
             loc: blk.loc.clone(),
             map: new_map,
         }))
     }
+
+    // True if frame was created by the apply() loop with the given caller.
+    fn is_frame_of(&self, frame: &Expr, caller: &Expr) -> bool {
+        match (&frame.caller(), caller) {
+            (Some(Expr::EDict(frame_caller)), Expr::EDict(caller)) => frame_caller.ptr_eq(caller),
+            _ => false,
+        }
+    }
+
+    // Finds @ in a block's defining scope, defaulting to the scope itself (e.g. at the top level).
+    fn lexical_self(&self, env: &Expr) -> Expr {
+        let at = "@".to_string();
+        match &self.find_scope(env, &at) {
+            Some(Expr::EDict(scope_ref)) => scope_ref.borrow().map[at.as_str()].clone(),
+            _ => env.clone(),
+        }
+    }
+}
+
+// Splits a trailing rest param's name from the params before it.
+fn split_rest(params: &[Param]) -> (&[Param], Option<&String>) {
+    match params.split_last() {
+        Some((Param { pattern: Pattern::Rest(name), .. }, init)) => (init, Some(name)),
+        _ => (params, None),
+    }
+}
+
+// Names a block for error messages: either 'name', or where an anonymous block was defined.
+fn block_name(block: &Block) -> String {
+    if block.loc.name.is_empty() {
+        format!("block at {}:{}:{}", block.loc.file, block.loc.pos.0, block.loc.pos.1)
+    } else {
+        format!("'{}'", block.loc.name)
+    }
+}
+
+// A step of apply(): either a result, or an apply (env, exprs) in tail position.
+// Tail applies may carry a self to wrap a resulting block with, as in maybe_wrap().
+enum Step {
+    Done(Expr),
+    Tail(Expr, Vec<Expr>, Option<Expr>),
 }
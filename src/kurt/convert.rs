@@ -0,0 +1,384 @@
+use std::{collections::HashMap, fmt, iter::FromIterator};
+
+use super::{
+    expr::{Expr, Key, _bool, _dict, _list, _num, _str, _NIL},
+    ordered_map::OrderedMap,
+};
+
+// Conversion from Kurt values to Rust values.
+pub trait FromExpr: Sized {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError>;
+}
+
+// Conversion from Rust values to Kurt values.
+pub trait IntoExpr {
+    fn into_expr(self) -> Expr;
+}
+
+// A failed conversion, e.g. `expected num, got "foo" (at [2].x)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub expected: String,
+    pub got: String,
+    // Location within a nested value, outermost first.
+    pub path: String,
+}
+
+impl TypeError {
+    pub fn new(expected: &str, got: &Expr) -> TypeError {
+        TypeError {
+            expected: expected.to_string(),
+            got: format!("{:#}", got),
+            path: String::default(),
+        }
+    }
+
+    // Prefixes the error's path with a list index or dict key.
+    fn at(mut self, segment: String) -> TypeError {
+        self.path = segment + &self.path;
+        self
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, got {}", self.expected, self.got)?;
+        if !self.path.is_empty() {
+            write!(f, " (at {})", self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromExpr for Expr {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+        Ok(expr.clone())
+    }
+}
+
+impl IntoExpr for Expr {
+    fn into_expr(self) -> Expr {
+        self
+    }
+}
+
+impl FromExpr for bool {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+        match expr {
+            Expr::EBool(b) => Ok(*b),
+            _ => Err(TypeError::new("bool", expr)),
+        }
+    }
+}
+
+impl IntoExpr for bool {
+    fn into_expr(self) -> Expr {
+        _bool(self)
+    }
+}
+
+impl FromExpr for f64 {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+        match expr {
+            Expr::ENum(x) => Ok(*x),
+            _ => Err(TypeError::new("num", expr)),
+        }
+    }
+}
+
+impl IntoExpr for f64 {
+    fn into_expr(self) -> Expr {
+        _num(self)
+    }
+}
+
+impl FromExpr for f32 {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+        f64::from_expr(expr).map(|x| x as f32)
+    }
+}
+
+impl IntoExpr for f32 {
+    fn into_expr(self) -> Expr {
+        _num(self as f64)
+    }
+}
+
+// Integers must be whole numbers within the target type's range. MAX as f64 rounds up for 64-bit
+// types (e.g. i64::MAX to 2^63, which would then saturate), so the upper bound is checked against
+// 2^bits instead, where bits is the number of value bits in MAX.
+macro_rules! int_conversions {
+    ($($t:ty),*) => {
+        $(
+            impl FromExpr for $t {
+                fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+                    match expr {
+                        Expr::ENum(x)
+                            if x.fract() == 0.0
+                                && *x >= <$t>::MIN as f64
+                                && *x < 2f64.powi(<$t>::MAX.count_ones() as i32) =>
+                        {
+                            Ok(*x as $t)
+                        }
+                        _ => Err(TypeError::new(stringify!($t), expr)),
+                    }
+                }
+            }
+
+            impl IntoExpr for $t {
+                fn into_expr(self) -> Expr {
+                    _num(self as f64)
+                }
+            }
+        )*
+    };
+}
+
+int_conversions!(i32, i64, u32, u64, usize);
+
+impl FromExpr for String {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+        match expr {
+            Expr::EStr(s) => Ok(s.clone()),
+            _ => Err(TypeError::new("str", expr)),
+        }
+    }
+}
+
+impl IntoExpr for String {
+    fn into_expr(self) -> Expr {
+        Expr::EStr(self)
+    }
+}
+
+impl IntoExpr for &str {
+    fn into_expr(self) -> Expr {
+        _str(self)
+    }
+}
+
+impl IntoExpr for () {
+    fn into_expr(self) -> Expr {
+        _NIL
+    }
+}
+
+// nil <-> None
+impl<T: FromExpr> FromExpr for Option<T> {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+        match expr {
+            Expr::ENil => Ok(None),
+            _ => T::from_expr(expr).map(Some),
+        }
+    }
+}
+
+impl<T: IntoExpr> IntoExpr for Option<T> {
+    fn into_expr(self) -> Expr {
+        match self {
+            Some(x) => x.into_expr(),
+            None => _NIL,
+        }
+    }
+}
+
+impl<T: FromExpr> FromExpr for Vec<T> {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+        match expr {
+            Expr::EList(list_ref) => list_ref
+                .borrow()
+                .exprs
+                .iter()
+                .enumerate()
+                .map(|(i, item)| T::from_expr(item).map_err(|err| err.at(format!("[{}]", i))))
+                .collect(),
+            _ => Err(TypeError::new("list", expr)),
+        }
+    }
+}
+
+impl<T: IntoExpr> IntoExpr for Vec<T> {
+    fn into_expr(self) -> Expr {
+        _list(self.into_iter().map(IntoExpr::into_expr).collect())
+    }
+}
+
+// Dicts convert to maps of their own keys. The parent (^) link is not included.
+// Maps keyed by String only accept dicts whose keys are all ids.
+impl<T: FromExpr> FromExpr for HashMap<String, T> {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+        dict_from_expr(expr, id_key)
+    }
+}
+
+impl<T: IntoExpr> IntoExpr for HashMap<String, T> {
+    fn into_expr(self) -> Expr {
+        _dict(
+            self.into_iter()
+                .map(|(key, value)| (key.into(), value.into_expr()))
+                .collect(),
+        )
+    }
+}
+
+// As with HashMap, but keeping the dict's order.
+impl<T: FromExpr> FromExpr for OrderedMap<String, T> {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+        dict_from_expr(expr, id_key)
+    }
+}
+
+impl<T: IntoExpr> IntoExpr for OrderedMap<String, T> {
+    fn into_expr(self) -> Expr {
+        _dict(
+            self.into_iter()
+                .map(|(key, value)| (key.into(), value.into_expr()))
+                .collect(),
+        )
+    }
+}
+
+// Maps keyed by Key accept any dict.
+impl<T: FromExpr> FromExpr for OrderedMap<Key, T> {
+    fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+        dict_from_expr(expr, |key| Ok(key.clone()))
+    }
+}
+
+impl<T: IntoExpr> IntoExpr for OrderedMap<Key, T> {
+    fn into_expr(self) -> Expr {
+        _dict(
+            self.into_iter()
+                .map(|(key, value)| (key, value.into_expr()))
+                .collect(),
+        )
+    }
+}
+
+fn dict_from_expr<K, T: FromExpr, M: FromIterator<(K, T)>>(
+    expr: &Expr,
+    convert_key: fn(&Key) -> Result<K, TypeError>,
+) -> Result<M, TypeError> {
+    match expr {
+        Expr::EDict(dict_ref) => dict_ref
+            .borrow()
+            .map
+            .iter()
+            .filter(|(key, _)| *key != "^")
+            .map(|(key, value)| {
+                let path = match key.as_id() {
+                    Some(name) => format!(".{}", name),
+                    None => format!("[{}]", key),
+                };
+                let key = convert_key(key).map_err(|err| err.at(path.clone()))?;
+                match T::from_expr(value) {
+                    Ok(value) => Ok((key, value)),
+                    Err(err) => Err(err.at(path)),
+                }
+            })
+            .collect(),
+        _ => Err(TypeError::new("dict", expr)),
+    }
+}
+
+fn id_key(key: &Key) -> Result<String, TypeError> {
+    match key.as_id() {
+        Some(name) => Ok(name.to_string()),
+        None => Err(TypeError::new("id key", &key.to_expr())),
+    }
+}
+
+// Tuples convert to lists of exactly the same length.
+macro_rules! tuple_conversions {
+    ($len:expr; $($t:ident $i:tt),*) => {
+        impl<$($t: FromExpr),*> FromExpr for ($($t,)*) {
+            fn from_expr(expr: &Expr) -> Result<Self, TypeError> {
+                let expected = concat!("list of ", stringify!($len));
+                match expr {
+                    Expr::EList(list_ref) => {
+                        let items = &list_ref.borrow().exprs;
+                        if items.len() != $len {
+                            return Err(TypeError::new(expected, expr));
+                        }
+                        Ok(($(
+                            $t::from_expr(&items[$i]).map_err(|err| err.at(format!("[{}]", $i)))?,
+                        )*))
+                    }
+                    _ => Err(TypeError::new(expected, expr)),
+                }
+            }
+        }
+
+        impl<$($t: IntoExpr),*> IntoExpr for ($($t,)*) {
+            fn into_expr(self) -> Expr {
+                _list(vec![$(self.$i.into_expr()),*])
+            }
+        }
+    };
+}
+
+tuple_conversions!(2; A 0, B 1);
+tuple_conversions!(3; A 0, B 1, C 2);
+tuple_conversions!(4; A 0, B 1, C 2, D 3);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use velcro::hash_map;
+
+    use crate::kurt::{expr::Key, ordered_map::OrderedMap, Kurt};
+
+    use super::{FromExpr, IntoExpr};
+
+    #[test]
+    fn convert() {
+        let kurt = Kurt::new();
+        let eval = |src: &str| kurt.run(&kurt.root, "convert", src).unwrap();
+
+        assert_eq!(Ok(42f64), f64::from_expr(&eval("42")));
+        assert_eq!(Ok(42i32), i32::from_expr(&eval("42")));
+        assert_eq!(Ok("foo".to_string()), String::from_expr(&eval("\"foo\"")));
+        assert_eq!(Ok(Some(true)), Option::<bool>::from_expr(&eval("true")));
+        assert_eq!(Ok(None), Option::<bool>::from_expr(&eval("nil")));
+        assert_eq!(Ok(vec![1u32, 2, 3]), Vec::<u32>::from_expr(&eval("[1 2 3]")));
+        assert_eq!(
+            Ok((1f64, "two".to_string(), false)),
+            <(f64, String, bool)>::from_expr(&eval("[1 \"two\" false]"))
+        );
+        assert_eq!(
+            Ok(hash_map! { "x".to_string(): 1f64, "y".to_string(): 2f64 }),
+            HashMap::<String, f64>::from_expr(&eval("{:x 1 :y 2}"))
+        );
+
+        let err = Vec::<f64>::from_expr(&eval("[1 2 \"three\"]")).err().unwrap();
+        assert_eq!("expected num, got \"three\" (at [2])", err.to_string());
+        let err = HashMap::<String, Vec<i32>>::from_expr(&eval("{:xs [1 2.5]}")).err().unwrap();
+        assert_eq!("expected i32, got 2.5 (at .xs[1])", err.to_string());
+        assert!(<(f64, f64)>::from_expr(&eval("[1 2 3]")).is_err());
+
+        // Integer bounds, including 64-bit ones that f64 can't represent exactly.
+        assert_eq!(Ok(i32::MAX), i32::from_expr(&eval("2147483647")));
+        assert!(i32::from_expr(&eval("2147483648")).is_err());
+        assert_eq!(Ok(i32::MIN), i32::from_expr(&eval("-2147483648")));
+        assert!(i32::from_expr(&eval("-2147483649")).is_err());
+        assert!(u32::from_expr(&eval("-1")).is_err());
+        assert_eq!(Ok(i64::MIN), i64::from_expr(&eval("-9223372036854775808")));
+        assert!(i64::from_expr(&eval("9223372036854775808")).is_err());
+        assert_eq!(Ok(1i64 << 62), i64::from_expr(&eval("4611686018427387904")));
+        assert!(u64::from_expr(&eval("18446744073709551616")).is_err());
+        assert_eq!(Ok(1u64 << 63), u64::from_expr(&eval("9223372036854775808")));
+
+        // Maps keyed by String need id keys; Key maps take any.
+        let err = HashMap::<String, f64>::from_expr(&eval("{:x 1 2 3}")).err().unwrap();
+        assert_eq!("expected id key, got 2 (at [2])", err.to_string());
+        let map = OrderedMap::<Key, f64>::from_expr(&eval("{:x 1 2 3}")).unwrap();
+        assert_eq!(Some(&3f64), map.get(&Key::from_expr(&eval("2")).unwrap()));
+        assert_eq!(Some(&1f64), map.get("x"));
+
+        let list = vec![(1f64, "a"), (2f64, "b")].into_expr();
+        assert_eq!(Ok(vec![(1f64, "a".to_string()), (2f64, "b".to_string())]), FromExpr::from_expr(&list));
+        assert!(Some(3i64).into_expr() == eval("3"));
+        assert!(None::<f64>.into_expr() == eval("nil"));
+    }
+}
@@ -0,0 +1,241 @@
+use std::{error::Error, fmt};
+
+use super::{
+    expr::{Expr, Key, _id, _qid, _NIL},
+    ordered_map::OrderedMap,
+    Exception, Kurt, Loc,
+};
+
+// Error returned to host code when Kurt evaluation fails.
+// Carries the exception's message, the exception dict itself, and the Kurt stack.
+#[derive(Clone)]
+pub struct KurtError {
+    pub message: String,
+    pub exception: Expr,
+    pub stack: Vec<Loc>,
+}
+
+impl From<Exception> for KurtError {
+    fn from(exception: Exception) -> Self {
+        let e = match exception {
+            Exception::Throw(e) => e,
+            // Control signals are normally turned into exceptions by Kurt::uncaught().
+            _ => {
+                return KurtError {
+                    message: "break, continue, or return outside of its block".into(),
+                    exception: _NIL,
+                    stack: Vec::new(),
+                }
+            }
+        };
+        let mut message = String::default();
+        let mut stack = Vec::<Loc>::new();
+        if let Expr::EDict(dict_ref) = &e {
+            let map = &dict_ref.borrow().map;
+            if let Some(Expr::EStr(s)) = map.get("message") {
+                message = s.clone();
+            }
+            if let Some(Expr::EList(list_ref)) = map.get("stack") {
+                for frame in &list_ref.borrow().exprs {
+                    if let Expr::EDict(frame_ref) = frame {
+                        stack.push(frame_loc(&frame_ref.borrow().map));
+                    }
+                }
+            }
+        }
+        KurtError {
+            message,
+            exception: e,
+            stack,
+        }
+    }
+}
+
+// Reads a { :file :name :pos [line col] } stack frame dict.
+fn frame_loc(map: &OrderedMap<Key, Expr>) -> Loc {
+    let mut loc = Loc::default();
+    if let Some(Expr::EStr(file)) = map.get("file") {
+        loc.file = file.clone();
+    }
+    if let Some(Expr::EStr(name)) = map.get("name") {
+        loc.name = name.clone();
+    }
+    if let Some(Expr::EList(pos_ref)) = map.get("pos") {
+        if let [Expr::ENum(line), Expr::ENum(col)] = pos_ref.borrow().exprs.as_slice() {
+            loc.pos = (*line as usize, *col as usize);
+        }
+    }
+    loc
+}
+
+impl fmt::Display for KurtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Expr::EDict(dict_ref) = &self.exception {
+            if let Some(Expr::EStr(source)) = dict_ref.borrow().map.get("source") {
+                write!(f, "\n  > {}", source)?;
+            }
+        }
+        for loc in &self.stack {
+            let name = if loc.name.is_empty() { "(unknown)" } else { &loc.name };
+            write!(f, "\n  {}", name)?;
+            if !loc.file.is_empty() {
+                write!(f, " {}", loc.file)?;
+            }
+            if loc.pos != (0, 0) {
+                write!(f, " [{} {}]", loc.pos.0, loc.pos.1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for KurtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KurtError")
+            .field("message", &self.message)
+            .field("stack", &self.stack)
+            .finish()
+    }
+}
+
+impl Error for KurtError {}
+
+impl Kurt {
+    // Evaluates source in the given env, returning the last value or the error that stopped it.
+    pub fn run(&self, env: &Expr, name: &str, src: &str) -> Result<Expr, KurtError> {
+        Ok(self.eval_forms(env, name, src)?)
+    }
+
+    // Loads and evaluates a file in root, returning the last value.
+    pub fn run_file(&self, filename: &str) -> Result<Expr, KurtError> {
+        let (key, src) = match self.load_file(filename) {
+            Ok(file) => file,
+            Err(msg) => return self.throw(&self.root, msg).map_err(KurtError::from),
+        };
+
+        self.loading.borrow_mut().push(key);
+        let result = self.eval_forms(&self.root, filename, src.as_str());
+        self.loading.borrow_mut().pop();
+        Ok(result?)
+    }
+
+    // Calls the block bound to `name` in root with already-evaluated args.
+    // Names may be dotted paths (e.g. "World.init"), in which case the block's @ is its container.
+    pub fn call(&self, name: &str, args: Vec<Expr>) -> Result<Expr, KurtError> {
+        let mut parts = name.split('.');
+        let mut target = self.get(&self.root, &_id(parts.next().unwrap_or("")))?;
+        for part in parts {
+            target = self.apply(&self.root, vec![target, _qid(part)])?;
+        }
+        let result = self.call_block(&self.root, &target, args);
+        Ok(self.uncaught(&self.root, result)?)
+    }
+
+    // Gets a binding in root (or its parents), if it exists.
+    pub fn get_global(&self, name: &str) -> Option<Expr> {
+        self.get(&self.root, &_id(name)).ok()
+    }
+
+    // Defines (or redefines) a binding in root.
+    pub fn set_global(&self, name: &str, value: Expr) {
+        if let Expr::EDict(root_ref) = &self.root {
+            root_ref.borrow_mut().map.insert(name.into(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use velcro::vec_from;
+
+    use crate::kurt::{
+        expr::{_num, _str, _NIL},
+        Kurt,
+    };
+
+    #[test]
+    fn embed() {
+        let kurt = Kurt::new();
+        kurt.run(
+            &kurt.root,
+            "embed",
+            "(def :Thing { :scale 2 :double (x | * x @.scale) })",
+        )
+        .unwrap();
+
+        kurt.set_global("answer", _num(21f64));
+        assert!(kurt.get_global("answer") == Some(_num(21f64)));
+        assert!(kurt.get_global("nothing").is_none());
+
+        let answer = kurt.get_global("answer").unwrap();
+        assert!(kurt.call("Thing.double", vec![answer]).unwrap() == _num(42f64));
+        assert!(kurt.run(&kurt.root, "embed", "(Thing.double answer)").unwrap() == _num(42f64));
+
+        let err = kurt.call("Thing.double", vec![_str("x")]).err().unwrap();
+        assert_eq!("operator requires numeric values", err.message);
+        assert_eq!("*", err.stack[0].name);
+
+        let err = kurt.run(&kurt.root, "embed", "(break)").err().unwrap();
+        assert_eq!("break outside of a loop", err.message);
+        kurt.run(&kurt.root, "embed", "(def :skip (| continue))").unwrap();
+        let err = kurt.call("skip", vec![]).err().unwrap();
+        assert_eq!("continue outside of a loop", err.message);
+
+        let err = kurt.run(&kurt.root, "embed", "(+ 1").err().unwrap();
+        assert_eq!(1, err.stack.len());
+        assert!(err.message.starts_with("parse error"));
+    }
+
+    #[test]
+    fn natives() {
+        let kurt = Kurt::new();
+
+        // Natives can capture host state.
+        let count = Rc::new(Cell::new(0f64));
+        let counter = count.clone();
+        kurt.add_native("bump", &vec_from!["by"], move |kurt, env| {
+            counter.set(counter.get() + kurt.loc_num(env, "by")?);
+            Ok(_num(counter.get()))
+        })
+        .unwrap();
+        assert!(kurt.run(&kurt.root, "natives", "(bump 2) (bump 3)").unwrap() == _num(5f64));
+        assert_eq!(5f64, count.get());
+
+        // Natives can be named and registered dynamically, including from other natives.
+        kurt.add_native("define", &vec_from!["name", "value"], |kurt, env| {
+            let name = kurt.loc_str(env, "name")?;
+            let value = kurt.loc(env, "value")?;
+            match kurt.add_native(name.as_str(), &vec_from![], move |_, _| Ok(value.clone())) {
+                Ok(()) => Ok(_NIL),
+                Err(e) => kurt.throw(env, e.message),
+            }
+        })
+        .unwrap();
+        kurt.run(&kurt.root, "natives", "(define \"answer\" 42)").unwrap();
+        assert!(kurt.run(&kurt.root, "natives", "(answer)").unwrap() == _num(42f64));
+        assert!(kurt.call("answer", vec![]).unwrap() == _num(42f64));
+
+        let err = kurt.call("bump", vec![_str("x")]).err().unwrap();
+        assert_eq!("by: expected num, got \"x\"", err.message);
+
+        // Param specs can give defaults for optional args, which can also be passed by keyword.
+        kurt.add_native("greet", &vec_from!["name", "greeting:\"hi\""], |kurt, env| {
+            let greeting = kurt.loc_str(env, "greeting")?;
+            Ok(_str(format!("{} {}", greeting, kurt.loc_str(env, "name")?).as_str()))
+        })
+        .unwrap();
+        assert!(kurt.call("greet", vec![_str("bob")]).unwrap() == _str("hi bob"));
+        let result = kurt.run(&kurt.root, "natives", "(greet {:name \"al\" :greeting \"yo\"}...)");
+        assert!(result.unwrap() == _str("yo al"));
+        let err = kurt.call("greet", vec![]).err().unwrap();
+        assert_eq!("'greet' expects 1 to 2 args (name greeting:\"hi\"), got 0", err.message);
+
+        // Bad param specs are reported, and leave nothing registered.
+        let err = kurt.add_native("oops", &vec_from!["x", "y:"], |_, _| Ok(_NIL)).err().unwrap();
+        assert!(err.message.starts_with("'oops': invalid param spec 'y:'"));
+        assert!(kurt.get_global("oops").is_none());
+    }
+}
@@ -1,18 +1,17 @@
-use std::{borrow::Borrow, collections::HashMap};
-
 use crate::kurt::{
-    expr::{Dict, List},
+    expr::{Dict, Key, List, _spread},
+    ordered_map::OrderedMap,
     Expr,
 };
 
 use super::{
     expr::{Apply, Assoc, Block},
-    ERef, Kurt,
+    ERef, Exception, Kurt,
 };
 
 impl Kurt {
     // Evaluates an expr within the given environment.
-    pub fn eval(&self, env: &Expr, expr: &Expr) -> Expr {
+    pub fn eval(&self, env: &Expr, expr: &Expr) -> Result<Expr, Exception> {
         if self.debug {
             // println!("eval -- {} :: {}", env, expr);
             println!("eval :: {}", expr);
@@ -32,58 +31,81 @@ impl Kurt {
             // Unquotes explicitly eval their exprs.
             Expr::EUnquote(s) => self.eval(env, &*s.borrow()),
 
+            // Spreads are only meaningful as args, where they're handled by invoke().
+            Expr::ESpread(_) => self.throw(env, format!("unexpected spread {}", expr)),
+
             // Except blocks, which capture their environment.
             Expr::EBlock(bref) => {
                 let b = &*bref.borrow();
                 if b.env == Expr::ENil {
                     // Grab the block's environment if one isn't already specified.
-                    Expr::EBlock(ERef::new(Block {
+                    Ok(Expr::EBlock(ERef::new(Block {
                         loc: b.loc.clone(),
                         params: b.params.clone(),
                         expr: b.expr.clone(),
                         env: env.clone(),
                         slf: b.slf.clone(),
-                    }))
+                        is_macro: b.is_macro,
+                    })))
                 } else {
-                    expr.clone()
+                    Ok(expr.clone())
                 }
             }
 
             // Associations evaluate to dicts, with their keys and values evaluated.
             // { expr expr ... } -> { [eval expr] : [eval expr] ... }
             Expr::EAssoc(assoc_ref) => {
-                let mut map = HashMap::<String, Expr>::new();
+                let mut map = OrderedMap::<Key, Expr>::new();
                 let assoc = &*assoc_ref.borrow();
                 for (key_expr, expr) in &assoc.pairs {
-                    let key = self.eval(env, &key_expr);
-                    if let Expr::EId(s) = &key {
-                        map.insert(s.clone(), self.eval(env, expr));
-                    } else {
-                        self.throw(env, format!("expected id key, got {}", key_expr))
+                    let key = self.eval(env, key_expr)?;
+                    let key = match Key::from_expr(&key) {
+                        Some(key) => key,
+                        None => return self.throw(env, format!("invalid dict key {:#}", key)),
+                    };
+                    let value = self.eval(env, expr)?;
+                    // Name anonymous blocks after their (id) keys, e.g. { :update (| ...) }.
+                    if let (Some(name), Expr::EBlock(block_ref)) = (key.as_id(), &value) {
+                        if block_ref.borrow().loc.name.is_empty() {
+                            block_ref.borrow_mut().loc.name = name.to_string();
+                        }
                     }
+                    map.insert(key, value);
                 }
-                Expr::EDict(ERef::new(Dict {
+                Ok(Expr::EDict(ERef::new(Dict {
                     loc: assoc.loc.clone(),
                     map: map,
-                }))
+                })))
             }
 
             // Lists also evaluate to themselves, with their values evaluated.
             // [expr ...] -> [ [eval expr] ...]
+            // Spread items ([xs... y]) are replaced by the items of the lists they evaluate to.
             Expr::EList(list_ref) => {
                 let list = &*list_ref.borrow();
-                let exprs = &list.exprs;
-                Expr::EList(ERef::new(List {
+                let mut exprs = Vec::<Expr>::new();
+                for expr in &list.exprs {
+                    match expr {
+                        Expr::ESpread(spread_ref) => match &self.eval(env, &spread_ref.borrow())? {
+                            Expr::EList(items_ref) => exprs.extend(items_ref.borrow().exprs.iter().cloned()),
+                            other => {
+                                return self.throw(env, format!("spread in a list requires a list, got {:#}", other))
+                            }
+                        },
+                        _ => exprs.push(self.eval(env, expr)?),
+                    }
+                }
+                Ok(Expr::EList(ERef::new(List {
                     loc: list.loc.clone(),
-                    exprs: exprs
-                        .into_iter()
-                        .map(|expr| self.eval(env, expr))
-                        .collect(),
-                }))
+                    exprs,
+                })))
             }
 
+            // Host objects are opaque, and evaluate to themselves.
+            Expr::EObject(_) => Ok(expr.clone()),
+
             // Dicts evaluate to themselves (their values were already evaluated in their apply form).
-            Expr::EDict(_) => expr.clone(),
+            Expr::EDict(_) => Ok(expr.clone()),
 
             // Apply (exprs...)
             Expr::EApply(vec_ref) => {
@@ -91,54 +113,99 @@ impl Kurt {
                 self.apply(env, exprs.clone())
             }
 
-            // Invoke native func.
-            Expr::ENative(name) => match self.builtins.get(name) {
-                Some(f) => f(self, env.borrow()),
-                _ => self.throw(env, format!("unimplemented builtin '{}'", name)),
-            },
+            // Invoke native func, completing any apply it left in tail position.
+            Expr::ENative(_) => {
+                let result = self.eval_native(env, expr)?;
+                let tail = self.tail.borrow_mut().take();
+                match tail {
+                    Some((next_env, next_exprs)) => self.apply(&next_env, next_exprs),
+                    None => Ok(result),
+                }
+            }
         }
     }
 
-    fn quote(&self, env: &Expr, expr: &Expr) -> Expr {
+    // Calls a native func.
+    // The native is cloned out first, so that it can itself register natives.
+    pub(crate) fn eval_native(&self, env: &Expr, expr: &Expr) -> Result<Expr, Exception> {
+        match expr {
+            Expr::ENative(name) => {
+                let native = self.builtins.borrow().get(name).cloned();
+                match native {
+                    Some(f) => f(self, env),
+                    _ => self.throw(env, format!("unimplemented builtin '{}'", name)),
+                }
+            }
+            _ => self.throw(env, format!("expected native, got {}", expr)),
+        }
+    }
+
+    fn quote(&self, env: &Expr, expr: &Expr) -> Result<Expr, Exception> {
         match &expr {
             Expr::EList(list_ref) => {
                 let list = &*list_ref.borrow();
                 let exprs = &list.exprs;
-                Expr::EList(ERef::new(List {
+                Ok(Expr::EList(ERef::new(List {
                     loc: list.loc.clone(),
-                    exprs: exprs
-                        .into_iter()
-                        .map(|expr| self.quote(env, expr))
-                        .collect(),
-                }))
+                    exprs: self.quote_items(env, exprs)?,
+                })))
             }
 
             Expr::EAssoc(assoc_ref) => {
                 let pairs = &assoc_ref.borrow().pairs;
-                Expr::EAssoc(ERef::new(Assoc {
+                Ok(Expr::EAssoc(ERef::new(Assoc {
                     pairs: pairs
                         .into_iter()
-                        .map(|pair| (self.quote(env, &pair.0), self.quote(env, &pair.1)))
-                        .collect(),
+                        .map(|pair| Ok((self.quote(env, &pair.0)?, self.quote(env, &pair.1)?)))
+                        .collect::<Result<Vec<(Expr, Expr)>, Exception>>()?,
                     loc: assoc_ref.borrow().loc.clone(),
-                }))
+                })))
             }
 
             Expr::EApply(apply_ref) => {
                 let apply = &*apply_ref.borrow();
                 let exprs = &apply.exprs;
-                Expr::EApply(ERef::new(Apply {
+                Ok(Expr::EApply(ERef::new(Apply {
                     loc: apply.loc.clone(),
-                    exprs: exprs
-                        .into_iter()
-                        .map(|expr| self.quote(env, expr))
-                        .collect(),
-                }))
+                    exprs: self.quote_items(env, exprs)?,
+                })))
+            }
+
+            // Blocks are quoted through their bodies, so that macros can fill in code to be run later.
+            Expr::EBlock(block_ref) => {
+                let block = &*block_ref.borrow();
+                Ok(Expr::EBlock(ERef::new(Block {
+                    loc: block.loc.clone(),
+                    params: block.params.clone(),
+                    expr: self.quote(env, &block.expr)?,
+                    env: block.env.clone(),
+                    slf: block.slf.clone(),
+                    is_macro: block.is_macro,
+                })))
             }
 
             Expr::EUnquote(eref) => self.eval(env, &*eref.borrow()),
+            Expr::ESpread(eref) => Ok(_spread(&self.quote(env, &eref.borrow())?)),
 
-            _ => expr.clone(),
+            _ => Ok(expr.clone()),
+        }
+    }
+
+    // Quotes the items of a list or apply, splicing in the items of unquoted spreads (\xs...).
+    fn quote_items(&self, env: &Expr, exprs: &Vec<Expr>) -> Result<Vec<Expr>, Exception> {
+        let mut items = Vec::<Expr>::new();
+        for expr in exprs {
+            if let Expr::ESpread(spread_ref) = expr {
+                if let Expr::EUnquote(unquote_ref) = &*spread_ref.borrow() {
+                    match &self.eval(env, &unquote_ref.borrow())? {
+                        Expr::EList(list_ref) => items.extend(list_ref.borrow().exprs.iter().cloned()),
+                        other => return self.throw(env, format!("splice requires a list, got {:#}", other)),
+                    }
+                    continue;
+                }
+            }
+            items.push(self.quote(env, expr)?);
         }
+        Ok(items)
     }
 }
@@ -1,9 +1,8 @@
-use std::collections::HashMap;
+use std::borrow::Borrow;
 use std::error::Error;
 use std::fmt;
 use std::fmt::Display;
-use std::panic::RefUnwindSafe;
-use std::panic::UnwindSafe;
+use std::hash::{Hash, Hasher};
 
 use gc::Finalize;
 use gc::Gc;
@@ -12,7 +11,7 @@ use gc::GcCellRef;
 use gc::GcCellRefMut;
 use gc::Trace;
 
-use super::Loc;
+use super::{object::Object, ordered_map::OrderedMap, Loc};
 
 // Expr represents both the AST and runtime state.
 // Parsing produces an expr graph, and evaluation updates that graph.
@@ -23,25 +22,23 @@ pub enum Expr {
     EBool(bool),
     EStr(String),
     EId(String),
-    ENative(&'static str),
+    ENative(String),
 
     EQuote(ERef<Expr>),
     EUnquote(ERef<Expr>),
+    ESpread(ERef<Expr>),
 
     EList(ERef<List>),
     EAssoc(ERef<Assoc>),
     EDict(ERef<Dict>),
     EBlock(ERef<Block>),
     EApply(ERef<Apply>),
+    EObject(ERef<Object>),
 }
 
 #[derive(Trace, Finalize, PartialEq, Clone)]
 pub struct Exprs(pub Vec<Expr>);
 
-// Needed for the use of expressions in the panic handler.
-impl UnwindSafe for Expr {}
-impl RefUnwindSafe for Expr {}
-
 #[derive(Trace, Finalize, PartialEq)]
 pub struct Assoc {
     pub loc: Loc,
@@ -51,7 +48,127 @@ pub struct Assoc {
 #[derive(Trace, Finalize, PartialEq)]
 pub struct Dict {
     pub loc: Loc,
-    pub map: HashMap<String, Expr>,
+    // Entries stay in insertion order, so that dicts print and iterate deterministically.
+    pub map: OrderedMap<Key, Expr>,
+}
+
+// A dict key: an id, str, num, or bool, compared by value (as with expr_eq()), or a list, dict,
+// block, or object, compared by identity.
+// Ids are stored as their names, so that they can be looked up by &str (e.g. map.get("^")).
+// Other keys are stored with a \0 and type tag prefix, so that they never collide with ids, and
+// keep their value, which also keeps reference keys alive (so their addresses aren't reused).
+#[derive(Trace, Finalize, Clone)]
+pub struct Key {
+    repr: String,
+    value: Option<Expr>,
+}
+
+impl Key {
+    // Makes a key from a value, if it can be one. NaN can't, as it isn't equal to itself.
+    pub fn from_expr(expr: &Expr) -> Option<Key> {
+        let repr = match expr {
+            Expr::EId(name) => return Some(Key::from(name.as_str())),
+            Expr::EStr(s) => format!("\0s{}", s),
+            Expr::ENum(n) if n.is_nan() => return None,
+            // -0 == 0, so they need the same repr.
+            Expr::ENum(n) if *n == 0f64 => format!("\0n{}", 0f64.to_bits()),
+            Expr::ENum(n) => format!("\0n{}", n.to_bits()),
+            Expr::EBool(b) => format!("\0b{}", b),
+            Expr::EList(r) => format!("\0r{}", r.addr()),
+            Expr::EDict(r) => format!("\0r{}", r.addr()),
+            Expr::EBlock(r) => format!("\0r{}", r.addr()),
+            Expr::EObject(r) => format!("\0r{}", r.addr()),
+            _ => return None,
+        };
+        Some(Key {
+            repr,
+            value: Some(expr.clone()),
+        })
+    }
+
+    // The key's value, e.g. for listing a dict's keys.
+    pub fn to_expr(&self) -> Expr {
+        match &self.value {
+            Some(value) => value.clone(),
+            None => Expr::EId(self.repr.clone()),
+        }
+    }
+
+    // The key's name, if it's an id.
+    pub fn as_id(&self) -> Option<&str> {
+        match self.value {
+            Some(_) => None,
+            None => Some(self.repr.as_str()),
+        }
+    }
+}
+
+// Strings convert to id keys.
+impl From<&str> for Key {
+    fn from(name: &str) -> Key {
+        Key {
+            repr: name.to_string(),
+            value: None,
+        }
+    }
+}
+
+impl From<String> for Key {
+    fn from(name: String) -> Key {
+        Key {
+            repr: name,
+            value: None,
+        }
+    }
+}
+
+impl Borrow<str> for Key {
+    fn borrow(&self) -> &str {
+        self.repr.as_str()
+    }
+}
+
+impl Borrow<String> for Key {
+    fn borrow(&self) -> &String {
+        &self.repr
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        self.repr == other.repr
+    }
+}
+
+impl Eq for Key {}
+
+// Compares with id keys, e.g. key == "^".
+impl PartialEq<str> for Key {
+    fn eq(&self, other: &str) -> bool {
+        self.repr == other
+    }
+}
+
+impl PartialEq<&str> for Key {
+    fn eq(&self, other: &&str) -> bool {
+        self.repr == *other
+    }
+}
+
+// Id keys print as quoted ids (:name), and others as their (readable) values.
+impl Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{:#}", value),
+            None => write!(f, ":{}", self.repr),
+        }
+    }
+}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.repr.hash(state)
+    }
 }
 
 #[derive(Trace, Finalize, PartialEq, Clone)]
@@ -70,10 +187,40 @@ pub struct Apply {
 #[derive(Trace, Finalize, PartialEq)]
 pub struct Block {
     pub loc: Loc,
-    pub params: Vec<String>,
+    pub params: Vec<Param>,
     pub expr: Expr,
     pub env: Expr,
     pub slf: Expr,
+    // Macros receive their args unevaluated, and yield code to evaluate in place of the call.
+    pub is_macro: bool,
+}
+
+// A block param -- (x y:default | ...).
+// Params with a default are optional. Params may also destructure their args (see Pattern).
+#[derive(Trace, Finalize, PartialEq, Clone)]
+pub struct Param {
+    pub pattern: Pattern,
+    pub default: Option<Expr>,
+}
+
+// The shape of a param, binding locals to its arg or the parts of it.
+#[derive(Trace, Finalize, PartialEq, Clone)]
+pub enum Pattern {
+    // x -- binds the whole value.
+    Id(String),
+    // x... -- binds any remaining args (or list items) as a list. Only valid as the last param.
+    Rest(String),
+    // [x y z] -- binds list items by position.
+    List(Vec<Param>),
+    // {:x :y} -- binds dict entries by key. Each key's value is bound to a param of the same
+    // name, or to a pattern following the key ({:pos [x y]}).
+    Dict(Vec<(String, Param)>),
+}
+
+impl Param {
+    pub fn is_rest(&self) -> bool {
+        matches!(self.pattern, Pattern::Rest(_))
+    }
 }
 
 // Utilities to simplify borrowing through ERefs.
@@ -120,6 +267,16 @@ impl<T: Trace> ERef<T> {
         self.0.try_borrow().map_err(|_| BorrowError)
     }
 
+    // True if both refs point to the same value.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Gc::ptr_eq(&self.0, &other.0)
+    }
+
+    // The address of the referenced value, which identifies it for as long as it's alive.
+    pub fn addr(&self) -> usize {
+        &*self.0 as *const GcCell<T> as usize
+    }
+
     pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T, T>, BorrowMutError> {
         self.0.try_borrow_mut().map_err(|_| BorrowMutError)
     }
@@ -166,14 +323,16 @@ impl Clone for Expr {
             Expr::EBool(x) => Expr::EBool(*x),
             Expr::EStr(x) => Expr::EStr(x.clone()),
             Expr::EId(x) => Expr::EId(x.clone()),
-            Expr::ENative(x) => Expr::ENative(*x),
+            Expr::ENative(x) => Expr::ENative(x.clone()),
             Expr::EList(r) => Expr::EList(r.clone()),
             Expr::EAssoc(r) => Expr::EAssoc(r.clone()),
             Expr::EDict(r) => Expr::EDict(r.clone()),
             Expr::EBlock(r) => Expr::EBlock(r.clone()),
             Expr::EApply(r) => Expr::EApply(r.clone()),
+            Expr::EObject(r) => Expr::EObject(r.clone()),
             Expr::EQuote(r) => Expr::EQuote(r.clone()),
             Expr::EUnquote(r) => Expr::EUnquote(r.clone()),
+            Expr::ESpread(r) => Expr::ESpread(r.clone()),
         }
     }
 }
@@ -208,7 +367,7 @@ pub fn _assoc(pairs: Vec<(Expr, Expr)>) -> Expr {
     }))
 }
 
-pub fn _dict(map: HashMap<String, Expr>) -> Expr {
+pub fn _dict(map: OrderedMap<Key, Expr>) -> Expr {
     Expr::EDict(ERef::new(Dict {
         loc: Loc::default(),
         map: map,
@@ -231,6 +390,10 @@ pub fn _uq(expr: &Expr) -> Expr {
     Expr::EUnquote(ERef::new(expr.clone()))
 }
 
+pub fn _spread(expr: &Expr) -> Expr {
+    Expr::ESpread(ERef::new(expr.clone()))
+}
+
 pub fn _app(exprs: Vec<Expr>) -> Expr {
     Expr::EApply(ERef::new(Apply {
         loc: Loc::default(),
@@ -238,13 +401,21 @@ pub fn _app(exprs: Vec<Expr>) -> Expr {
     }))
 }
 
+pub fn _param(name: &str) -> Param {
+    Param {
+        pattern: Pattern::Id(name.into()),
+        default: None,
+    }
+}
+
 pub fn _block(params: Vec<String>, expr: Expr) -> Expr {
     Expr::EBlock(ERef::new(Block {
         loc: Loc::default(),
-        params: params,
+        params: params.iter().map(|name| _param(name)).collect(),
         expr: expr,
         env: _NIL,
         slf: _NIL,
+        is_macro: false,
     }))
 }
 
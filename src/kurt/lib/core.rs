@@ -1,212 +1,379 @@
-use std::panic;
-
 use velcro::vec_from;
 
 use crate::kurt::{
-    expr::{Expr, _bool, _id, _FALSE, _NIL, _TRUE},
-    Kurt,
+    embed::KurtError,
+    expr::{Block, ERef, Expr, Key, _app, _bool, _id, _FALSE, _NIL, _TRUE},
+    ordered_map::OrderedMap,
+    Exception, Kurt,
 };
 
 use super::eq::expr_eq;
 
 impl Kurt {
-    pub fn init_core(&mut self) {
-        self.add_builtin("do", &vec_from!["exprs..."], Kurt::native_do);
-        self.add_builtin("def", &vec_from!["name", "value"], Kurt::native_def);
-        self.add_builtin("def-all", &vec_from!["values"], Kurt::native_def_all);
-        self.add_builtin("let", &vec_from!["vars", "expr"], Kurt::native_let);
-        self.add_builtin("set", &vec_from!["name", "value"], Kurt::native_set);
-        self.add_builtin("set-all", &vec_from!["values"], Kurt::native_set_all);
-        self.add_builtin("if", &vec_from!["cond", "if", "else"], Kurt::native_if);
-        self.add_builtin("?", &vec_from!["id"], Kurt::native_exists);
-        self.add_builtin("try", &vec_from!["block", "catch"], Kurt::native_try);
-        self.add_builtin("print", &vec_from!["msgs..."], Kurt::native_print);
-
-        self.add_builtin("=", &vec_from!["x", "y"], Kurt::native_eq);
-        self.add_builtin("!=", &vec_from!["x", "y"], Kurt::native_neq);
-
-        self.add_builtin("test", &vec_from!["name", "expr"], Kurt::native_test);
-        self.add_builtin("expect", &vec_from!["expect", "expr"], Kurt::native_expect);
-
-        self.add_builtin("not", &vec_from!["x"], Kurt::native_not);
-
-        self.eval_file("./src/kurt/lib/core.kurt");
-
-        // Override panic handler to suppress automatic stack traces.
-        panic::set_hook(Box::new(|info| {
-            // Kind of a hack -- panics created by (throw) will contain the string [exception].
-            let s = format!("{}", info);
-            if !s.contains("[exception]") {
-                println!("{}", s);
-            }
-        }));
+    pub fn init_core(&mut self) -> Result<(), KurtError> {
+        self.add_builtin("do", &vec_from!["exprs..."], Kurt::native_do)?;
+        self.add_builtin("def", &vec_from!["name", "value"], Kurt::native_def)?;
+        self.add_builtin("defmacro", &vec_from!["name", "block"], Kurt::native_defmacro)?;
+        self.add_builtin("macroexpand", &vec_from!["form"], Kurt::native_macroexpand)?;
+        self.add_builtin("def-all", &vec_from!["values"], Kurt::native_def_all)?;
+        self.add_builtin("let", &vec_from!["vars", "expr"], Kurt::native_let)?;
+        self.add_builtin("set", &vec_from!["name", "value"], Kurt::native_set)?;
+        self.add_builtin("set-all", &vec_from!["values"], Kurt::native_set_all)?;
+        self.add_builtin("if", &vec_from!["cond", "if", "else:nil"], Kurt::native_if)?;
+        self.add_builtin("when", &vec_from!["cond", "body"], Kurt::native_when)?;
+        self.add_builtin("unless", &vec_from!["cond", "body"], Kurt::native_unless)?;
+        self.add_builtin("cond", &vec_from!["clauses..."], Kurt::native_cond)?;
+        self.add_builtin("and", &vec_from!["conds..."], Kurt::native_and)?;
+        self.add_builtin("or", &vec_from!["conds..."], Kurt::native_or)?;
+        self.add_builtin("?", &vec_from!["id"], Kurt::native_exists)?;
+        self.add_builtin("try", &vec_from!["block", "catch"], Kurt::native_try)?;
+        self.add_builtin("apply", &vec_from!["block", "args", "kwargs:nil"], Kurt::native_apply)?;
+        self.add_builtin("print", &vec_from!["msgs..."], Kurt::native_print)?;
+        self.add_builtin("parse", &vec_from!["src"], Kurt::native_parse)?;
+        self.add_builtin("import", &vec_from!["path"], Kurt::native_import)?;
+        self.add_builtin("require", &vec_from!["path"], Kurt::native_require)?;
+
+        self.add_builtin("=", &vec_from!["x", "y"], Kurt::native_eq)?;
+        self.add_builtin("!=", &vec_from!["x", "y"], Kurt::native_neq)?;
+
+        self.add_builtin("test", &vec_from!["name", "expr"], Kurt::native_test)?;
+        self.add_builtin("expect", &vec_from!["expect", "expr"], Kurt::native_expect)?;
+
+        self.add_builtin("not", &vec_from!["x"], Kurt::native_not)?;
+
+        self.eval_file("std/core")?;
+        Ok(())
     }
 
-    fn native_eq(&self, env: &Expr) -> Expr {
-        let _a = self.loc(env, "x");
-        let _b = self.loc(env, "y");
-        _bool(expr_eq(_a, _b))
+    fn native_eq(&self, env: &Expr) -> Result<Expr, Exception> {
+        let _a = self.loc(env, "x")?;
+        let _b = self.loc(env, "y")?;
+        Ok(_bool(expr_eq(_a, _b)))
     }
 
-    fn native_neq(&self, env: &Expr) -> Expr {
-        let _a = self.loc(env, "x");
-        let _b = self.loc(env, "y");
-        _bool(!expr_eq(_a, _b))
+    fn native_neq(&self, env: &Expr) -> Result<Expr, Exception> {
+        let _a = self.loc(env, "x")?;
+        let _b = self.loc(env, "y")?;
+        Ok(_bool(!expr_eq(_a, _b)))
     }
 
-    fn native_do(&self, env: &Expr) -> Expr {
-        let exprs = self.loc(&env, "exprs...");
+    fn native_do(&self, env: &Expr) -> Result<Expr, Exception> {
+        let exprs = self.loc(env, "exprs")?;
         match &exprs {
             Expr::EList(vec_ref) => {
-                let mut last = _NIL;
-                for expr in &vec_ref.borrow().exprs {
-                    last = self.apply(&env, vec![expr.clone()])
+                let exprs = &vec_ref.borrow().exprs;
+                match exprs.split_last() {
+                    Some((last, init)) => {
+                        for expr in init {
+                            self.apply(env, vec![expr.clone()])?;
+                        }
+                        self.apply_tail(env, vec![last.clone()])
+                    }
+                    None => Ok(_NIL),
                 }
-                last
             }
-            _ => exprs,
+            _ => Ok(exprs),
+        }
+    }
+
+    // (let {vars} expr) evaluates expr with vars as its env.
+    // (let value (pattern | expr)) instead binds value to the block's param, destructuring it.
+    fn native_let(&self, env: &Expr) -> Result<Expr, Exception> {
+        let vars = self.loc(env, "vars")?;
+        let expr = self.loc(env, "expr")?;
+        match &expr {
+            Expr::EBlock(block_ref) if !block_ref.borrow().params.is_empty() => {
+                self.call_block_tail(env, &expr, vec![vars], OrderedMap::new())
+            }
+            _ => self.apply_tail(env, vec![vars, expr]),
         }
     }
 
-    fn native_let(&self, env: &Expr) -> Expr {
-        let vars = self.loc(&env, "vars");
-        let expr = self.loc(&env, "expr");
-        self.apply(env, vec![vars, expr])
+    // (apply block [args...] {kwargs}) -- as (block args... {kwargs}...).
+    fn native_apply(&self, env: &Expr) -> Result<Expr, Exception> {
+        let block = self.loc(env, "block")?;
+        let args = self.loc_list(env, "args")?;
+        let kwargs = self.loc_as::<Option<OrderedMap<String, Expr>>>(env, "kwargs")?;
+        self.call_block_tail(env, &block, args, kwargs.unwrap_or_default())
     }
 
-    fn native_def(&self, env: &Expr) -> Expr {
-        let this = self.loc(&env, "@");
-        let name = self.loc(&env, "name");
-        let value = self.loc(&env, "value");
+    fn native_def(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let name = self.loc(env, "name")?;
+        let value = self.loc(env, "value")?;
 
         name_block(&name, &value);
-        self.def(&this, &name, &value);
-        this
+        self.def(&this, &name, &value)?;
+        Ok(this)
+    }
+
+    // (defmacro :name (args | code)) defines a macro, which is invoked with its args unevaluated
+    // and yields code to evaluate in place of its call. Code is usually built with quotes, which
+    // keep the locations of both the macro's template and the args spliced into it.
+    fn native_defmacro(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let name = self.loc(env, "name")?;
+        let block = self.loc(env, "block")?;
+        let mac = match &block {
+            Expr::EBlock(block_ref) => {
+                let block = &*block_ref.borrow();
+                Expr::EBlock(ERef::new(Block {
+                    loc: block.loc.clone(),
+                    params: block.params.clone(),
+                    expr: block.expr.clone(),
+                    env: block.env.clone(),
+                    slf: block.slf.clone(),
+                    is_macro: true,
+                }))
+            }
+            _ => return self.throw(env, format!("defmacro requires a block, got {:#}", block)),
+        };
+
+        name_block(&name, &mac);
+        self.def(&this, &name, &mac)?;
+        Ok(this)
     }
 
-    fn native_def_all(&self, env: &Expr) -> Expr {
-        let this = self.loc(&env, "@");
-        let values = self.loc(&env, "values");
+    // (macroexpand :(form ...)) expands a form for as long as it's a macro call, without
+    // evaluating the result.
+    fn native_macroexpand(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let mut form = self.loc(env, "form")?;
+        loop {
+            let exprs = match &form {
+                Expr::EApply(apply_ref) => apply_ref.borrow().exprs.clone(),
+                _ => return Ok(form),
+            };
+            let mac = match exprs.first() {
+                Some(Expr::EId(name)) => match self.find_scope(&this, name) {
+                    Some(scope) => self.get(&scope, &_id(name))?,
+                    None => return Ok(form),
+                },
+                _ => return Ok(form),
+            };
+            match &mac {
+                Expr::EBlock(block_ref) if block_ref.borrow().is_macro => {
+                    form = self.call_block(&this, &mac, exprs[1..].to_vec())?;
+                }
+                _ => return Ok(form),
+            }
+        }
+    }
+
+    fn native_def_all(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let values = self.loc(env, "values")?;
         match &values {
             Expr::EDict(dict_ref) => {
                 let dict = &*dict_ref.borrow();
-                for (name, value) in &dict.map {
-                    let name_expr = _id(name.as_str());
-                    name_block(&name_expr, &value);
-                    self.def(&this, &name_expr, &value);
+                for (key, value) in &dict.map {
+                    let key_expr = key.to_expr();
+                    name_block(&key_expr, value);
+                    self.def(&this, &key_expr, value)?;
                 }
-                this
+                Ok(this)
             }
             _ => self.throw(env, "def_all takes dict".into()),
         }
     }
 
-    fn native_set(&self, env: &Expr) -> Expr {
-        let this = self.loc(&env, "@");
-        let name = self.loc(&env, "name");
-        let value = self.loc(&env, "value");
+    fn native_set(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let name = self.loc(env, "name")?;
+        let value = self.loc(env, "value")?;
 
         name_block(&name, &value);
-        self.set(&this, &name, &value);
-        this
+        self.set(&this, &name, &value)?;
+        Ok(this)
     }
 
-    fn native_set_all(&self, env: &Expr) -> Expr {
-        let this = self.loc(&env, "@");
-        let values = self.loc(&env, "values");
+    fn native_set_all(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let values = self.loc(env, "values")?;
         match &values {
             Expr::EDict(dict_ref) => {
                 let dict = &*dict_ref.borrow();
-                for (name, value) in &dict.map {
-                    let name_expr = _id(name.as_str());
-                    name_block(&name_expr, &value);
-                    self.def(&this, &name_expr, &value);
+                for (key, value) in &dict.map {
+                    let key_expr = key.to_expr();
+                    name_block(&key_expr, value);
+                    self.def(&this, &key_expr, value)?;
                 }
-                this
+                Ok(this)
             }
             _ => self.throw(env, "def_all takes dict".into()),
         }
     }
 
-    fn native_if(kurt: &Kurt, env: &Expr) -> Expr {
-        let cond = kurt.loc(env, "cond");
-        let _if = kurt.loc(env, "if");
-        let _else = kurt.loc(env, "else");
-        match &cond {
-            Expr::EBool(b) => {
-                if *b {
-                    kurt.apply(env, vec![_if.clone()])
-                } else {
-                    kurt.apply(env, vec![_else.clone()])
-                }
+    // Conditions must be bools -- there's no implicit truthiness, so nil, 0, "", and [] are all
+    // errors rather than false. Blocks are applied to get their condition, which allows lazy
+    // conditions, as in (and x (| y)).
+    pub fn truth(&self, env: &Expr, form: &str, cond: Expr) -> Result<bool, Exception> {
+        let value = match &cond {
+            Expr::EBlock(_) => self.apply(env, vec![cond.clone()])?,
+            _ => cond,
+        };
+        match value {
+            Expr::EBool(b) => Ok(b),
+            other => self.throw(env, format!("{} requires a bool condition, got {:#}", form, other)),
+        }
+    }
+
+    fn native_if(kurt: &Kurt, env: &Expr) -> Result<Expr, Exception> {
+        let cond = kurt.loc(env, "cond")?;
+        let _if = kurt.loc(env, "if")?;
+        let _else = kurt.loc(env, "else")?;
+        if kurt.truth(env, "if", cond)? {
+            kurt.apply_tail(env, vec![_if.clone()])
+        } else {
+            kurt.apply_tail(env, vec![_else.clone()])
+        }
+    }
+
+    fn native_when(&self, env: &Expr) -> Result<Expr, Exception> {
+        let cond = self.loc(env, "cond")?;
+        let body = self.loc(env, "body")?;
+        match self.truth(env, "when", cond)? {
+            true => self.apply_tail(env, vec![body]),
+            false => Ok(_NIL),
+        }
+    }
+
+    fn native_unless(&self, env: &Expr) -> Result<Expr, Exception> {
+        let cond = self.loc(env, "cond")?;
+        let body = self.loc(env, "body")?;
+        match self.truth(env, "unless", cond)? {
+            true => Ok(_NIL),
+            false => self.apply_tail(env, vec![body]),
+        }
+    }
+
+    // (cond [cond body] ...) -- applies the body of the first clause whose condition is true.
+    // Yields nil if there is none; use [true body] for a fallback.
+    fn native_cond(&self, env: &Expr) -> Result<Expr, Exception> {
+        for clause in self.loc_list(env, "clauses")? {
+            let (cond, body) = match cond_clause(&clause) {
+                Some(pair) => pair,
+                None => return self.throw(env, format!("invalid cond clause {:#}", clause)),
+            };
+            if self.truth(env, "cond", cond)? {
+                return self.apply_tail(env, vec![body]);
+            }
+        }
+        Ok(_NIL)
+    }
+
+    // (and x y ...) and (or x y ...) stop at the first false (or true) condition.
+    fn native_and(&self, env: &Expr) -> Result<Expr, Exception> {
+        for cond in self.loc_list(env, "conds")? {
+            if !self.truth(env, "and", cond)? {
+                return Ok(_FALSE);
             }
-            _ => kurt.throw(env, format!("")),
         }
+        Ok(_TRUE)
     }
 
-    fn native_exists(kurt: &Kurt, env: &Expr) -> Expr {
-        let this = kurt.loc(env, "@");
-        let id = kurt.loc(env, "id");
-        match &id {
-            Expr::EId(name) => match kurt.find_scope(&this, &name) {
-                Some(_) => _TRUE,
-                _ => _FALSE,
+    fn native_or(&self, env: &Expr) -> Result<Expr, Exception> {
+        for cond in self.loc_list(env, "conds")? {
+            if self.truth(env, "or", cond)? {
+                return Ok(_TRUE);
+            }
+        }
+        Ok(_FALSE)
+    }
+
+    fn native_exists(kurt: &Kurt, env: &Expr) -> Result<Expr, Exception> {
+        let this = kurt.loc(env, "@")?;
+        let id = kurt.loc(env, "id")?;
+        match Key::from_expr(&id) {
+            Some(key) => match kurt.find_scope(&this, &key) {
+                Some(_) => Ok(_TRUE),
+                _ => Ok(_FALSE),
             },
-            _ => _FALSE,
+            _ => Ok(_FALSE),
         }
     }
 
-    fn native_print(&self, env: &Expr) -> Expr {
-        let list = self.loc_list(&env, "msgs...");
+    fn native_print(&self, env: &Expr) -> Result<Expr, Exception> {
+        let list = self.loc_list(env, "msgs")?;
         for expr in list {
             if expr != _NIL {
                 print!("{} ", expr);
             }
         }
         println!();
-        _NIL
+        Ok(_NIL)
     }
 
-    fn native_try(&self, env: &Expr) -> Expr {
-        let block = self.loc(&env, "block");
-        let catch = self.loc(&env, "catch");
-        match (&block, &catch) {
-            (Expr::EBlock(_), Expr::EBlock(_)) => {
-                match panic::catch_unwind(|| self.apply(&env, vec![block.clone()])) {
-                    Ok(result) => result,
-                    Err(_) => match self.exception.replace(None) {
-                        Some(e) => self.apply(&env, vec![catch.clone(), e]),
-                        None => self.apply(&env, vec![catch.clone()]),
-                    },
+    fn native_parse(&self, env: &Expr) -> Result<Expr, Exception> {
+        let src = self.loc_str(env, "src")?;
+        let mut exprs = self.parse(env, "parse", src.as_str())?;
+        match exprs.len() {
+            0 => Ok(_NIL),
+            1 => Ok(exprs.pop().unwrap()),
+            // Multiple forms become (do form...).
+            _ => {
+                exprs.insert(0, _id("do"));
+                Ok(_app(exprs))
+            }
+        }
+    }
+
+    fn native_import(&self, env: &Expr) -> Result<Expr, Exception> {
+        let path = self.loc_str(env, "path")?;
+        self.import(env, path.as_str())
+    }
+
+    // Imports a module and defines all of its bindings in the current env.
+    fn native_require(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let path = self.loc_str(env, "path")?;
+        let module = self.import(env, path.as_str())?;
+        if let Expr::EDict(dict_ref) = &module {
+            for (name, value) in &dict_ref.borrow().map {
+                if name != "^" {
+                    self.def(&this, &name.to_expr(), value)?;
                 }
             }
+        }
+        Ok(module)
+    }
+
+    fn native_try(&self, env: &Expr) -> Result<Expr, Exception> {
+        let block = self.loc(env, "block")?;
+        let catch = self.loc(env, "catch")?;
+        match (&block, &catch) {
+            (Expr::EBlock(_), Expr::EBlock(_)) => match self.apply(env, vec![block.clone()]) {
+                Ok(result) => Ok(result),
+                Err(Exception::Throw(e)) => self.apply(env, vec![catch.clone(), e]),
+                Err(signal) => Err(signal),
+            },
             (_, _) => self.throw(env, "try requires body and catch blocks".to_string()),
         }
     }
 
-    fn native_test(&self, env: &Expr) -> Expr {
-        let name = self.loc_str(env, "name");
-        let expr = self.loc(env, "expr");
+    fn native_test(&self, env: &Expr) -> Result<Expr, Exception> {
+        let name = self.loc_str(env, "name")?;
+        let expr = self.loc(env, "expr")?;
         println!("-[ {} ]-", name);
-        self.apply(&env, vec![expr.clone()]);
+        self.apply(env, vec![expr.clone()])?;
         println!();
-        _NIL
+        Ok(_NIL)
     }
 
-    fn native_expect(&self, env: &Expr) -> Expr {
-        let expect = self.loc(env, "expect");
-        let expr = self.loc(env, "expr");
+    fn native_expect(&self, env: &Expr) -> Result<Expr, Exception> {
+        let expect = self.loc(env, "expect")?;
+        let expr = self.loc(env, "expr")?;
         if !expr_eq(expect.clone(), expr.clone()) {
-            self.throw(
+            return self.throw(
                 env,
-                format!("expected {} : got {}", expect.clone(), expr.clone()),
+                format!("expected {:#} : got {:#}", expect.clone(), expr.clone()),
             );
         }
-        _NIL
+        Ok(_NIL)
     }
 
-    fn native_not(&self, env: &Expr) -> Expr {
-        _bool(!self.loc_bool(&env, "x"))
+    fn native_not(&self, env: &Expr) -> Result<Expr, Exception> {
+        Ok(_bool(!self.loc_bool(env, "x")?))
     }
 }
 
@@ -221,6 +388,17 @@ fn name_block(name: &Expr, value: &Expr) {
     }
 }
 
+// Splits a (cond ...) clause into its condition and body.
+fn cond_clause(clause: &Expr) -> Option<(Expr, Expr)> {
+    match clause {
+        Expr::EList(list_ref) => match &list_ref.borrow().exprs[..] {
+            [cond, body] => Some((cond.clone(), body.clone())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::kurt::Kurt;
@@ -235,3 +413,4 @@ mod tests {
         Kurt::test_file("src/kurt/lib/core_obj_test.kurt");
     }
 }
+
@@ -1,15 +1,158 @@
-use velcro::{hash_map, vec_from};
+use velcro::{map_iter, vec_from};
 
-use crate::kurt::{Kurt, expr::_dict};
+use crate::kurt::{
+    embed::KurtError,
+    expr::{Key, _dict, _list, _num, _NIL},
+    ordered_map::OrderedMap,
+    Exception, Expr, Kurt,
+};
 
 impl Kurt {
-    pub fn init_dict(&mut self) {
-        self.def_dict = _dict(hash_map! {
-            "set".into(): self.builtin("set", &vec_from!["name", "value"]),
-            "set-all".into(): self.builtin("set-all", &vec_from!["values"]),
-            "def".into(): self.builtin("def", &vec_from!["name", "value"]),
-            "def-all".into(): self.builtin("def-all", &vec_from!["values"]),
-            "?".into(): self.builtin("?", &vec_from!["id"]),
-        });
+    pub fn init_dict(&mut self) -> Result<(), KurtError> {
+        self.add_builtin("dict:get", &vec_from!["key"], Kurt::native_dict_get)?;
+        self.add_builtin("dict:iter", &vec_from!["block"], Kurt::native_dict_iter)?;
+        self.add_builtin("dict:keys", &vec_from![], Kurt::native_dict_keys)?;
+        self.add_builtin("dict:values", &vec_from![], Kurt::native_dict_values)?;
+        self.add_builtin("dict:entries", &vec_from![], Kurt::native_dict_entries)?;
+        self.add_builtin("dict:len", &vec_from![], Kurt::native_dict_len)?;
+        self.add_builtin("dict:has", &vec_from!["key"], Kurt::native_dict_has)?;
+        self.add_builtin("dict:remove", &vec_from!["key"], Kurt::native_dict_remove)?;
+        self.add_builtin("dict:merge", &vec_from!["dicts..."], Kurt::native_dict_merge)?;
+        self.add_builtin("dict:map-values", &vec_from!["block"], Kurt::native_dict_map_values)?;
+
+        self.def_dict = _dict(map_iter! {
+            "set".into(): self.builtin("set", &vec_from!["name", "value"])?,
+            "set-all".into(): self.builtin("set-all", &vec_from!["values"])?,
+            "def".into(): self.builtin("def", &vec_from!["name", "value"])?,
+            "def-all".into(): self.builtin("def-all", &vec_from!["values"])?,
+            "?".into(): self.builtin("?", &vec_from!["id"])?,
+            "get".into(): self.builtin("dict:get", &vec_from!["key"])?,
+            "iter".into(): self.builtin("dict:iter", &vec_from!["block"])?,
+            "keys".into(): self.builtin("dict:keys", &vec_from![])?,
+            "values".into(): self.builtin("dict:values", &vec_from![])?,
+            "entries".into(): self.builtin("dict:entries", &vec_from![])?,
+            "len".into(): self.builtin("dict:len", &vec_from![])?,
+            "has".into(): self.builtin("dict:has", &vec_from!["key"])?,
+            "remove".into(): self.builtin("dict:remove", &vec_from!["key"])?,
+            "merge".into(): self.builtin("dict:merge", &vec_from!["dicts..."])?,
+            "map-values".into(): self.builtin("dict:map-values", &vec_from!["block"])?,
+        }.collect());
+        Ok(())
+    }
+
+    // (d.get key) looks up a key of any kind in the dict and its parents, e.g. (ents.get 42).
+    // (d key) can't be used for non-id keys, as they evaluate to themselves within the dict.
+    // As with has, the dict methods aren't keys, so (d.get :len) only finds a len entry.
+    fn native_dict_get(&self, env: &Expr) -> Result<Expr, Exception> {
+        let key = self.loc_key(env, "get")?;
+        match self.dict_entry(&self.loc(env, "@")?, &key) {
+            Some(value) => Ok(value),
+            None => self.throw(env, format!("{} not found", key)),
+        }
+    }
+
+    // The methods below only see a dict's own entries, excluding its parent (^) link.
+
+    // Calls (block key value) for each entry. Loops over a copy, so that the block can modify the dict.
+    fn native_dict_iter(&self, env: &Expr) -> Result<Expr, Exception> {
+        let map = self.loc_as::<OrderedMap<Key, Expr>>(env, "@")?;
+        let block = self.loc(env, "block")?;
+        for (key, value) in map {
+            if let Some(result) = self.iterate(env, &block, vec![key.to_expr(), value])? {
+                return Ok(result);
+            }
+        }
+        Ok(_NIL)
+    }
+
+    fn native_dict_keys(&self, env: &Expr) -> Result<Expr, Exception> {
+        let map = self.loc_as::<OrderedMap<Key, Expr>>(env, "@")?;
+        Ok(_list(map.into_keys().map(|key| key.to_expr()).collect()))
+    }
+
+    fn native_dict_values(&self, env: &Expr) -> Result<Expr, Exception> {
+        let map = self.loc_as::<OrderedMap<Key, Expr>>(env, "@")?;
+        Ok(_list(map.into_values().collect()))
+    }
+
+    // A list of [key value] pairs.
+    fn native_dict_entries(&self, env: &Expr) -> Result<Expr, Exception> {
+        let map = self.loc_as::<OrderedMap<Key, Expr>>(env, "@")?;
+        Ok(_list(map.into_iter().map(|(key, value)| _list(vec![key.to_expr(), value])).collect()))
+    }
+
+    fn native_dict_len(&self, env: &Expr) -> Result<Expr, Exception> {
+        let map = self.loc_as::<OrderedMap<Key, Expr>>(env, "@")?;
+        Ok(_num(map.len() as f64))
+    }
+
+    // Whether the dict itself has the key. Unlike (? key), parents aren't searched.
+    fn native_dict_has(&self, env: &Expr) -> Result<Expr, Exception> {
+        let key = self.loc_key(env, "has")?;
+        let map = self.loc_as::<OrderedMap<Key, Expr>>(env, "@")?;
+        Ok(Expr::EBool(map.contains_key(&key)))
+    }
+
+    // Removes the key from the dict, yielding its value (or nil if it wasn't there).
+    fn native_dict_remove(&self, env: &Expr) -> Result<Expr, Exception> {
+        let key = self.loc_key(env, "remove")?;
+        match &self.loc(env, "@")? {
+            Expr::EDict(dict_ref) if key != "^" => Ok(dict_ref.borrow_mut().map.remove(&key).unwrap_or(_NIL)),
+            Expr::EDict(_) => Ok(_NIL),
+            other => self.throw(env, format!("remove requires a dict, got {:#}", other)),
+        }
+    }
+
+    // A new dict with the entries of this dict and then each of the others, so that later keys win.
+    // The new dict keeps this dict's parent.
+    fn native_dict_merge(&self, env: &Expr) -> Result<Expr, Exception> {
+        let mut map = self.loc_as::<OrderedMap<Key, Expr>>(env, "@")?;
+        if let Some(parent) = self.dict_parent(&self.loc(env, "@")?) {
+            map.insert("^".into(), parent);
+        }
+        for other in self.loc_as::<Vec<OrderedMap<Key, Expr>>>(env, "dicts")? {
+            map.extend(other);
+        }
+        Ok(_dict(map))
+    }
+
+    // A new dict with each value replaced by (block value). The new dict keeps this dict's parent.
+    fn native_dict_map_values(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc_as::<OrderedMap<Key, Expr>>(env, "@")?;
+        let block = self.loc(env, "block")?;
+        let mut map = OrderedMap::<Key, Expr>::new();
+        for (key, value) in this {
+            map.insert(key, self.call_block(env, &block, vec![value])?);
+        }
+        if let Some(parent) = self.dict_parent(&self.loc(env, "@")?) {
+            map.insert("^".into(), parent);
+        }
+        Ok(_dict(map))
+    }
+
+    // Reads the "key" local, which must be something that can be a key (i.e. not nil or NaN).
+    fn loc_key(&self, env: &Expr, method: &str) -> Result<Key, Exception> {
+        let key = self.loc(env, "key")?;
+        match Key::from_expr(&key) {
+            Some(key) => Ok(key),
+            None => self.throw(env, format!("{} requires a valid key, got {:#}", method, key)),
+        }
+    }
+
+    fn dict_parent(&self, dict: &Expr) -> Option<Expr> {
+        match dict {
+            Expr::EDict(dict_ref) => dict_ref.borrow().map.get("^").cloned(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kurt::Kurt;
+
+    #[test]
+    fn dict() {
+        Kurt::test_file("src/kurt/lib/dict_test.kurt");
     }
 }
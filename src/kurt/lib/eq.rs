@@ -90,7 +90,11 @@ pub fn expr_eq(_a: Expr, _b: Expr) -> bool {
                 if a_map.len() == b_map.len() {
                     eq = true;
                     for (k, v) in a_map {
-                        if !expr_eq(v.clone(), b_map[k].clone()) {
+                        let same = match b_map.get(k) {
+                            Some(b_v) => expr_eq(v.clone(), b_v.clone()),
+                            None => false,
+                        };
+                        if !same {
                             eq = false;
                             break;
                         }
@@ -131,6 +135,23 @@ pub fn expr_eq(_a: Expr, _b: Expr) -> bool {
             }
         }
 
+        Expr::ESpread(a) => {
+            if let Expr::ESpread(b) = &_b {
+                expr_eq((*a.borrow()).clone(), (*b.borrow()).clone())
+            } else {
+                false
+            }
+        }
+
+        // Host objects are only equal to themselves.
+        Expr::EObject(a_ref) => {
+            if let Expr::EObject(b_ref) = &_b {
+                *a_ref.borrow() == *b_ref.borrow()
+            } else {
+                false
+            }
+        }
+
         // TODO: Ref equality on blocks, etc?
         _ => false,
     }
@@ -0,0 +1,322 @@
+use gc::{Finalize, Trace};
+use velcro::{map_iter, vec_from};
+
+use crate::kurt::{
+    embed::KurtError,
+    expr::{Expr, Key, _dict, _list, _num, _NIL},
+    ordered_map::OrderedMap,
+    Exception, Kurt,
+};
+
+use super::eq::expr_eq;
+
+// A lazy numeric range, from start (inclusive) to end (exclusive).
+#[derive(Trace, Finalize, Clone)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+impl Range {
+    fn len(&self) -> usize {
+        ((self.end - self.start) / self.step).ceil().max(0f64) as usize
+    }
+}
+
+impl Kurt {
+    pub fn init_flow(&mut self) -> Result<(), KurtError> {
+        self.add_builtin("while", &vec_from!["cond", "body"], Kurt::native_while)?;
+        self.add_builtin("loop", &vec_from!["body"], Kurt::native_loop)?;
+        self.add_builtin("break", &vec_from!["value:nil"], Kurt::native_break)?;
+        self.add_builtin("continue", &vec_from![], Kurt::native_continue)?;
+        self.add_builtin("return", &vec_from!["value:nil"], Kurt::native_return)?;
+        self.add_builtin("return-from", &vec_from!["name", "value:nil"], Kurt::native_return_from)?;
+        self.add_builtin("range", &vec_from!["start", "end:nil", "step:1"], Kurt::native_range)?;
+        self.add_builtin("match", &vec_from!["value", "clauses..."], Kurt::native_match)?;
+
+        self.add_builtin("range:len", &vec_from![], Kurt::native_range_len)?;
+        self.add_builtin("range:iter", &vec_from!["block"], Kurt::native_range_iter)?;
+        self.def_host_type::<Range>(
+            "range",
+            _dict(map_iter! {
+                "len".into(): self.builtin("range:len", &vec_from![])?,
+                "iter".into(): self.builtin("range:iter", &vec_from!["block"])?,
+            }.collect()),
+        );
+        Ok(())
+    }
+
+    // Runs one iteration of a loop body, returning the break value if it broke out of the loop.
+    // Loops evaluate to nil, or to the value passed to (break value).
+    pub fn iterate(&self, env: &Expr, block: &Expr, args: Vec<Expr>) -> Result<Option<Expr>, Exception> {
+        match self.call_block(env, block, args) {
+            Ok(_) | Err(Exception::Continue) => Ok(None),
+            Err(Exception::Break(value)) => Ok(Some(value)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn native_while(&self, env: &Expr) -> Result<Expr, Exception> {
+        let cond = self.loc(env, "cond")?;
+        let body = self.loc(env, "body")?;
+        loop {
+            if !self.truth(env, "while", cond.clone())? {
+                return Ok(_NIL);
+            }
+            if let Some(value) = self.iterate(env, &body, vec![])? {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn native_loop(&self, env: &Expr) -> Result<Expr, Exception> {
+        let body = self.loc(env, "body")?;
+        loop {
+            if let Some(value) = self.iterate(env, &body, vec![])? {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn native_break(&self, env: &Expr) -> Result<Expr, Exception> {
+        let value = self.loc(env, "value")?;
+        Err(Exception::Break(value))
+    }
+
+    fn native_continue(&self, _: &Expr) -> Result<Expr, Exception> {
+        Err(Exception::Continue)
+    }
+
+    // Returns from the nearest enclosing named block, e.g. one bound with (def ...).
+    // Anonymous blocks, such as (| ...) branches and loop bodies, are skipped.
+    fn native_return(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let value = self.loc(env, "value")?;
+        match self.return_target(&this, None) {
+            Some(frame) => Err(Exception::Return(frame, value)),
+            None => self.throw(env, "return outside of a named block".into()),
+        }
+    }
+
+    // Returns from the nearest enclosing block with the given name.
+    fn native_return_from(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let value = self.loc(env, "value")?;
+        let name = match &self.loc(env, "name")? {
+            Expr::EId(name) => name.clone(),
+            other => return self.throw(env, format!("return-from requires a block name, got {:#}", other)),
+        };
+        match self.return_target(&this, Some(&name)) {
+            Some(frame) => Err(Exception::Return(frame, value)),
+            None => self.throw(env, format!("return-from outside of a block named '{}'", name)),
+        }
+    }
+
+    // Finds the nearest block frame in env's lexical (^) chain that is named (with the given name,
+    // if any). Frames are the dicts created by new_frame(), and carry their block's loc.
+    fn return_target(&self, env: &Expr, name: Option<&str>) -> Option<Expr> {
+        let mut cur = env.clone();
+        loop {
+            let dict_ref = match &cur {
+                Expr::EDict(dict_ref) => dict_ref.clone(),
+                _ => return None,
+            };
+            let dict = &*dict_ref.borrow();
+            let is_frame = dict.map.contains_key("caller");
+            if is_frame && !dict.loc.name.is_empty() && name.map_or(true, |name| name == dict.loc.name) {
+                return Some(cur);
+            }
+            cur = dict.map.get("^")?.clone();
+        }
+    }
+
+    // (match value [:pattern body] [:pattern (| guard) body] ...)
+    // Evaluates the body of the first clause whose pattern matches the value (and whose guard, if
+    // any, returns true), with the pattern's bindings as locals. Bodies are usually blocks, so that
+    // only the chosen one is evaluated. Patterns are quoted data:
+    // - x binds the value to x, and _ matches anything.
+    // - Literals (42, "foo", true, nil) match equal values, as do quoted ids (::foo, or :foo within
+    //   a larger pattern).
+    // - [x y] matches lists of the same length item-by-item, and [x rest...] any longer ones.
+    // - {:key pattern} matches dicts with the given keys, which may also be strs, nums, or bools.
+    // - (type pattern) matches values of a type (e.g. (num n)), and then the (optional) pattern.
+    fn native_match(&self, env: &Expr) -> Result<Expr, Exception> {
+        let value = self.loc(env, "value")?;
+        for clause in self.loc_list(env, "clauses")? {
+            let (pattern, guard, body) = match &clause {
+                Expr::EList(list_ref) => match &list_ref.borrow().exprs[..] {
+                    [pattern, body] => (pattern.clone(), None, body.clone()),
+                    [pattern, guard, body] => (pattern.clone(), Some(guard.clone()), body.clone()),
+                    _ => return self.throw(env, format!("invalid match clause {:#}", clause)),
+                },
+                _ => return self.throw(env, format!("invalid match clause {:#}", clause)),
+            };
+
+            let mut bindings = OrderedMap::<Key, Expr>::new();
+            if !self.match_pattern(env, &pattern, &value, &mut bindings)? {
+                continue;
+            }
+            let bindings = _dict(bindings);
+            if let Some(guard) = guard {
+                match self.apply(env, vec![bindings.clone(), guard])? {
+                    Expr::EBool(true) => (),
+                    Expr::EBool(false) => continue,
+                    other => return self.throw(env, format!("match guard must return a bool, got {:#}", other)),
+                }
+            }
+            return self.apply_tail(env, vec![bindings, body]);
+        }
+        self.throw(env, format!("no match for {:#}", value))
+    }
+
+    // Matches a value against a pattern (see native_match()), adding its bindings.
+    fn match_pattern(
+        &self,
+        env: &Expr,
+        pattern: &Expr,
+        value: &Expr,
+        bindings: &mut OrderedMap<Key, Expr>,
+    ) -> Result<bool, Exception> {
+        match pattern {
+            Expr::EId(name) if name == "_" => Ok(true),
+            Expr::EId(name) => {
+                bindings.insert(name.as_str().into(), value.clone());
+                Ok(true)
+            }
+            Expr::EQuote(quoted) => Ok(expr_eq(quoted.borrow().clone(), value.clone())),
+
+            Expr::EList(list_ref) => {
+                let items = match value {
+                    Expr::EList(items_ref) => items_ref.borrow().exprs.clone(),
+                    _ => return Ok(false),
+                };
+                let patterns = list_ref.borrow().exprs.clone();
+                let (patterns, rest) = match patterns.split_last() {
+                    Some((Expr::ESpread(rest_ref), init)) => (init.to_vec(), Some(rest_ref.borrow().clone())),
+                    _ => (patterns, None),
+                };
+                let len_ok = match rest {
+                    Some(_) => items.len() >= patterns.len(),
+                    None => items.len() == patterns.len(),
+                };
+                if !len_ok {
+                    return Ok(false);
+                }
+                for (pattern, item) in patterns.iter().zip(items.iter()) {
+                    if !self.match_pattern(env, pattern, item, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                match rest {
+                    Some(rest) => self.match_pattern(env, &rest, &_list(items[patterns.len()..].to_vec()), bindings),
+                    None => Ok(true),
+                }
+            }
+
+            Expr::EAssoc(assoc_ref) => {
+                if !matches!(value, Expr::EDict(_)) {
+                    return Ok(false);
+                }
+                for (key, pattern) in &assoc_ref.borrow().pairs {
+                    let key = match key {
+                        Expr::EQuote(quoted) => match &*quoted.borrow() {
+                            Expr::EId(key) => Key::from(key.as_str()),
+                            _ => return self.throw(env, format!("invalid match key {}", key)),
+                        },
+                        Expr::EStr(_) | Expr::ENum(_) | Expr::EBool(_) => match Key::from_expr(key) {
+                            Some(key) => key,
+                            None => return self.throw(env, format!("invalid match key {}", key)),
+                        },
+                        _ => return self.throw(env, format!("invalid match key {}", key)),
+                    };
+                    // As with dict params, the dict methods (e.g. keys) don't count as keys.
+                    let item = match self.dict_entry(value, &key) {
+                        Some(item) => item,
+                        None => return Ok(false),
+                    };
+                    if !self.match_pattern(env, pattern, &item, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+
+            Expr::EApply(apply_ref) => match &apply_ref.borrow().exprs[..] {
+                [Expr::EId(type_name)] => Ok(*type_name == self.type_name(value)),
+                [Expr::EId(type_name), pattern] => {
+                    Ok(*type_name == self.type_name(value) && self.match_pattern(env, pattern, value, bindings)?)
+                }
+                _ => self.throw(env, format!("invalid type pattern {}", pattern)),
+            },
+
+            Expr::ESpread(_) => self.throw(env, format!("rest pattern {} must end a list", pattern)),
+
+            _ => Ok(expr_eq(pattern.clone(), value.clone())),
+        }
+    }
+
+    // The name of a value's type, as used by (type pattern) in match.
+    pub fn type_name(&self, value: &Expr) -> String {
+        match value {
+            Expr::ENil => "nil".into(),
+            Expr::EBool(_) => "bool".into(),
+            Expr::ENum(_) => "num".into(),
+            Expr::EStr(_) => "str".into(),
+            Expr::EId(_) => "id".into(),
+            Expr::EList(_) => "list".into(),
+            Expr::EAssoc(_) | Expr::EDict(_) => "dict".into(),
+            Expr::EBlock(_) => "block".into(),
+            Expr::EObject(obj_ref) => obj_ref.borrow().name.clone(),
+            _ => "expr".into(),
+        }
+    }
+
+    // (range end), (range start end), or (range start end step).
+    fn native_range(&self, env: &Expr) -> Result<Expr, Exception> {
+        let first = self.loc_num(env, "start")?;
+        let range = match self.loc_as::<Option<f64>>(env, "end")? {
+            Some(end) => Range {
+                start: first,
+                end,
+                step: self.loc_num(env, "step")?,
+            },
+            None => Range {
+                start: 0f64,
+                end: first,
+                step: 1f64,
+            },
+        };
+        if range.step == 0f64 {
+            return self.throw(env, "range step must not be zero".into());
+        }
+        Ok(self.object(range))
+    }
+
+    fn native_range_len(&self, env: &Expr) -> Result<Expr, Exception> {
+        let range = self.loc_object::<Range>(env, "@")?;
+        Ok(_num(range.len() as f64))
+    }
+
+    fn native_range_iter(&self, env: &Expr) -> Result<Expr, Exception> {
+        let range = self.loc_object::<Range>(env, "@")?;
+        let block = self.loc(env, "block")?;
+        for i in 0..range.len() {
+            let x = range.start + range.step * i as f64;
+            if let Some(value) = self.iterate(env, &block, vec![_num(x)])? {
+                return Ok(value);
+            }
+        }
+        Ok(_NIL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kurt::Kurt;
+
+    #[test]
+    fn flow() {
+        Kurt::test_file("src/kurt/lib/flow_test.kurt");
+    }
+}
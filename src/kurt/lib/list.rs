@@ -1,57 +1,91 @@
-use velcro::{hash_map, vec_from};
+use velcro::{map_iter, vec_from};
 
 use crate::kurt::{
-    expr::{Expr, _dict, _NIL, _num},
-    Kurt,
+    embed::KurtError,
+    expr::{Expr, _dict, _list, _num, _NIL},
+    Exception, Kurt,
 };
 
+use super::eq::expr_eq;
+
 impl Kurt {
-    pub fn init_list(&mut self) {
-        self.add_builtin("list:len", &vec_from![""], Kurt::native_list_len);
-        self.add_builtin("list:iter", &vec_from!["block"], Kurt::native_list_iter);
-        self.add_builtin("list:push", &vec_from!["value"], Kurt::native_list_push);
-        self.add_builtin("list:pop", &vec_from![""], Kurt::native_list_pop);
-
-        self.def_list = _dict(hash_map! {
-            "set".into(): self.builtin("set", &vec_from!["name", "value"]),
-            "len".into(): self.builtin("list:len", &vec_from![]),
-            "iter".into(): self.builtin("list:iter", &vec_from!["block"]),
-            "push".into(): self.builtin("list:push", &vec_from!["value"]),
-            "pop".into(): self.builtin("list:pop", &vec_from![]),
-        });
-    }
-
-    fn native_list_len(&self, env: &Expr) -> Expr {
-        let this = self.loc(env, "@");
+    pub fn init_list(&mut self) -> Result<(), KurtError> {
+        self.add_builtin("list:len", &vec_from![], Kurt::native_list_len)?;
+        self.add_builtin("list:iter", &vec_from!["block"], Kurt::native_list_iter)?;
+        self.add_builtin("list:push", &vec_from!["value"], Kurt::native_list_push)?;
+        self.add_builtin("list:pop", &vec_from![], Kurt::native_list_pop)?;
+        self.add_builtin("list:insert", &vec_from!["index", "value"], Kurt::native_list_insert)?;
+        self.add_builtin("list:remove", &vec_from!["index"], Kurt::native_list_remove)?;
+        self.add_builtin("list:map", &vec_from!["block"], Kurt::native_list_map)?;
+        self.add_builtin("list:filter", &vec_from!["block"], Kurt::native_list_filter)?;
+        self.add_builtin("list:reduce", &vec_from!["init", "block"], Kurt::native_list_reduce)?;
+        self.add_builtin("list:find", &vec_from!["block"], Kurt::native_list_find)?;
+        self.add_builtin("list:any", &vec_from!["block"], Kurt::native_list_any)?;
+        self.add_builtin("list:all", &vec_from!["block"], Kurt::native_list_all)?;
+        self.add_builtin("list:sort", &vec_from!["block:nil"], Kurt::native_list_sort)?;
+        self.add_builtin("list:reverse", &vec_from![], Kurt::native_list_reverse)?;
+        self.add_builtin("list:slice", &vec_from!["start", "end:nil"], Kurt::native_list_slice)?;
+        self.add_builtin("list:concat", &vec_from!["lists..."], Kurt::native_list_concat)?;
+        self.add_builtin("list:index-of", &vec_from!["value"], Kurt::native_list_index_of)?;
+        self.add_builtin("list:zip", &vec_from!["other"], Kurt::native_list_zip)?;
+        self.add_builtin("list:enum", &vec_from![], Kurt::native_list_enum)?;
+
+        self.def_list = _dict(map_iter! {
+            "set".into(): self.builtin("set", &vec_from!["name", "value"])?,
+            "len".into(): self.builtin("list:len", &vec_from![])?,
+            "iter".into(): self.builtin("list:iter", &vec_from!["block"])?,
+            "push".into(): self.builtin("list:push", &vec_from!["value"])?,
+            "pop".into(): self.builtin("list:pop", &vec_from![])?,
+            "insert".into(): self.builtin("list:insert", &vec_from!["index", "value"])?,
+            "remove".into(): self.builtin("list:remove", &vec_from!["index"])?,
+            "map".into(): self.builtin("list:map", &vec_from!["block"])?,
+            "filter".into(): self.builtin("list:filter", &vec_from!["block"])?,
+            "reduce".into(): self.builtin("list:reduce", &vec_from!["init", "block"])?,
+            "find".into(): self.builtin("list:find", &vec_from!["block"])?,
+            "any".into(): self.builtin("list:any", &vec_from!["block"])?,
+            "all".into(): self.builtin("list:all", &vec_from!["block"])?,
+            "sort".into(): self.builtin("list:sort", &vec_from!["block:nil"])?,
+            "reverse".into(): self.builtin("list:reverse", &vec_from![])?,
+            "slice".into(): self.builtin("list:slice", &vec_from!["start", "end:nil"])?,
+            "concat".into(): self.builtin("list:concat", &vec_from!["lists..."])?,
+            "index-of".into(): self.builtin("list:index-of", &vec_from!["value"])?,
+            "zip".into(): self.builtin("list:zip", &vec_from!["other"])?,
+            "enum".into(): self.builtin("list:enum", &vec_from![])?,
+        }.collect());
+        Ok(())
+    }
+
+    fn native_list_len(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
         match &this {
             Expr::EList(list_ref) => {
                 let list = &mut *list_ref.borrow_mut();
-                _num(list.exprs.len() as f64)
+                Ok(_num(list.exprs.len() as f64))
             }
             _ => self.throw(env, "len requires a list".into()),
         }
     }
 
-    fn native_list_push(&self, env: &Expr) -> Expr {
-        let this = self.loc(env, "@");
-        let value = self.loc(env, "value");
+    fn native_list_push(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let value = self.loc(env, "value")?;
         match &this {
             Expr::EList(list_ref) => {
                 let list = &mut *list_ref.borrow_mut();
                 list.exprs.push(value);
-                _NIL
+                Ok(_NIL)
             }
             _ => self.throw(env, "push requires a lit".into()),
         }
     }
 
-    fn native_list_pop(&self, env: &Expr) -> Expr {
-        let this = self.loc(env, "@");
+    fn native_list_pop(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
         match &this {
             Expr::EList(list_ref) => {
                 let list = &mut *list_ref.borrow_mut();
                 match list.exprs.pop() {
-                    Some(expr) => expr.clone(),
+                    Some(expr) => Ok(expr.clone()),
                     None => self.throw(env, "attempted to pop an empty list".into()),
                 }
             }
@@ -59,21 +93,244 @@ impl Kurt {
         }
     }
 
-    fn native_list_iter(&self, env: &Expr) -> Expr {
-        let this = self.loc(env, "@");
-        let block = self.loc(env, "block");
+    // Inserts value before the item at index (or at the end, if index is the list's length).
+    fn native_list_insert(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let index = self.loc_num(env, "index")?;
+        let value = self.loc(env, "value")?;
+        match &this {
+            Expr::EList(list_ref) => {
+                let list = &mut *list_ref.borrow_mut();
+                match list_index(index, list.exprs.len() + 1) {
+                    Some(i) => {
+                        list.exprs.insert(i, value);
+                        Ok(_NIL)
+                    }
+                    None => self.throw(env, format!("insert index {} out of range", index)),
+                }
+            }
+            _ => self.throw(env, "insert requires a list".into()),
+        }
+    }
+
+    // Removes the item at index, yielding it.
+    fn native_list_remove(&self, env: &Expr) -> Result<Expr, Exception> {
+        let this = self.loc(env, "@")?;
+        let index = self.loc_num(env, "index")?;
         match &this {
             Expr::EList(list_ref) => {
-                let list = &*list_ref.borrow();
-                for i in 0..list.exprs.len() {
-                    let item = list.exprs.get(i).unwrap();
-                    self.apply(env, vec![block.clone(), _num(i as f64), item.clone()]);
+                let list = &mut *list_ref.borrow_mut();
+                match list_index(index, list.exprs.len()) {
+                    Some(i) => Ok(list.exprs.remove(i)),
+                    None => self.throw(env, format!("remove index {} out of range", index)),
+                }
+            }
+            _ => self.throw(env, "remove requires a list".into()),
+        }
+    }
+
+    // Calls (block i item) for each item. Loops over a copy, so that the block can modify the list.
+    fn native_list_iter(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let block = self.loc(env, "block")?;
+        for (i, item) in items.into_iter().enumerate() {
+            if let Some(value) = self.iterate(env, &block, vec![_num(i as f64), item])? {
+                return Ok(value);
+            }
+        }
+        Ok(_NIL)
+    }
+
+    // The methods below leave the list unchanged, yielding new lists where needed.
+
+    fn native_list_map(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let block = self.loc(env, "block")?;
+        let mut result = Vec::<Expr>::new();
+        for item in items {
+            result.push(self.call_block(env, &block, vec![item])?);
+        }
+        Ok(_list(result))
+    }
+
+    fn native_list_filter(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let block = self.loc(env, "block")?;
+        let mut result = Vec::<Expr>::new();
+        for item in items {
+            if self.test_item(env, "filter", &block, &item)? {
+                result.push(item);
+            }
+        }
+        Ok(_list(result))
+    }
+
+    // (xs.reduce init (acc item | ...)) folds the items into an accumulator, starting with init.
+    fn native_list_reduce(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let block = self.loc(env, "block")?;
+        let mut acc = self.loc(env, "init")?;
+        for item in items {
+            acc = self.call_block(env, &block, vec![acc, item])?;
+        }
+        Ok(acc)
+    }
+
+    // The first item for which the block returns true, or nil.
+    fn native_list_find(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let block = self.loc(env, "block")?;
+        for item in items {
+            if self.test_item(env, "find", &block, &item)? {
+                return Ok(item);
+            }
+        }
+        Ok(_NIL)
+    }
+
+    fn native_list_any(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let block = self.loc(env, "block")?;
+        for item in items {
+            if self.test_item(env, "any", &block, &item)? {
+                return Ok(Expr::EBool(true));
+            }
+        }
+        Ok(Expr::EBool(false))
+    }
+
+    fn native_list_all(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let block = self.loc(env, "block")?;
+        for item in items {
+            if !self.test_item(env, "all", &block, &item)? {
+                return Ok(Expr::EBool(false));
+            }
+        }
+        Ok(Expr::EBool(true))
+    }
+
+    // Calls a predicate block on an item, which must return a bool.
+    fn test_item(&self, env: &Expr, form: &str, block: &Expr, item: &Expr) -> Result<bool, Exception> {
+        match self.call_block(env, block, vec![item.clone()])? {
+            Expr::EBool(b) => Ok(b),
+            other => self.throw(env, format!("{} block must return a bool, got {:#}", form, other)),
+        }
+    }
+
+    // Sorts (stably) by (block a b), which returns true if a belongs before b.
+    // Without a block, nums, strs, and ids sort in ascending order.
+    fn native_list_sort(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let block = self.loc(env, "block")?;
+        let less = |a: &Expr, b: &Expr| -> Result<bool, Exception> {
+            match (&block, a, b) {
+                (Expr::ENil, Expr::ENum(a), Expr::ENum(b)) => Ok(a < b),
+                (Expr::ENil, Expr::EStr(a), Expr::EStr(b)) => Ok(a < b),
+                (Expr::ENil, Expr::EId(a), Expr::EId(b)) => Ok(a < b),
+                (Expr::ENil, _, _) => {
+                    self.throw(env, format!("sort requires nums, strs, or ids (or a block), got {:#} and {:#}", a, b))
                 }
+                _ => match self.call_block(env, &block, vec![a.clone(), b.clone()])? {
+                    Expr::EBool(b) => Ok(b),
+                    other => self.throw(env, format!("sort block must return a bool, got {:#}", other)),
+                },
             }
-            _ => self.throw(env, "iter requires a list".into()),
+        };
+        Ok(_list(merge_sort(items, &less)?))
+    }
+
+    fn native_list_reverse(&self, env: &Expr) -> Result<Expr, Exception> {
+        let mut items = self.loc_list(env, "@")?;
+        items.reverse();
+        Ok(_list(items))
+    }
+
+    // (xs.slice start end) -> items [start, end), where end defaults to the end of the list.
+    // Indices are clamped to the list.
+    fn native_list_slice(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let start = self.loc_num(env, "start")?.max(0f64) as usize;
+        let end = match self.loc_as::<Option<f64>>(env, "end")? {
+            Some(end) => end.max(0f64) as usize,
+            None => items.len(),
+        };
+        let end = end.min(items.len());
+        let start = start.min(end);
+        Ok(_list(items[start..end].to_vec()))
+    }
+
+    fn native_list_concat(&self, env: &Expr) -> Result<Expr, Exception> {
+        let mut items = self.loc_list(env, "@")?;
+        for list in self.loc_as::<Vec<Vec<Expr>>>(env, "lists")? {
+            items.extend(list);
+        }
+        Ok(_list(items))
+    }
+
+    // The index of the first item equal to value, or nil.
+    fn native_list_index_of(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let value = self.loc(env, "value")?;
+        match items.into_iter().position(|item| expr_eq(item, value.clone())) {
+            Some(i) => Ok(_num(i as f64)),
+            None => Ok(_NIL),
+        }
+    }
+
+    // Pairs up the items of two lists, stopping at the end of the shorter.
+    fn native_list_zip(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let other = self.loc_list(env, "other")?;
+        let pairs = items.into_iter().zip(other).map(|(a, b)| _list(vec![a, b]));
+        Ok(_list(pairs.collect()))
+    }
+
+    // Pairs each item with its index, as [i item] (e.g. for map or filter, which pass only the item).
+    fn native_list_enum(&self, env: &Expr) -> Result<Expr, Exception> {
+        let items = self.loc_list(env, "@")?;
+        let pairs = items.into_iter().enumerate().map(|(i, item)| _list(vec![_num(i as f64), item]));
+        Ok(_list(pairs.collect()))
+    }
+}
+
+// Converts a Kurt index to a usize, if it's a whole number below len.
+fn list_index(index: f64, len: usize) -> Option<usize> {
+    if index >= 0f64 && index.fract() == 0f64 && (index as usize) < len {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+// A stable merge sort with a fallible comparison, which (unlike slice::sort_by()) tolerates
+// inconsistent orderings from user blocks.
+fn merge_sort<F>(items: Vec<Expr>, less: &F) -> Result<Vec<Expr>, Exception>
+where
+    F: Fn(&Expr, &Expr) -> Result<bool, Exception>,
+{
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(left, less)?;
+    let right = merge_sort(right, less)?;
+
+    let mut result = Vec::<Expr>::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Take from the right only if strictly less, to keep equal items in order.
+        if less(b, a)? {
+            result.push(right.next().unwrap());
+        } else {
+            result.push(left.next().unwrap());
         }
-        _NIL
     }
+    result.extend(left);
+    result.extend(right);
+    Ok(result)
 }
 
 #[cfg(test)]
@@ -1,54 +1,90 @@
-use super::{Kurt, Loc, expr::{Block, ERef, Expr, _id, _NIL}};
+use std::rc::Rc;
+
+use super::{
+    convert::FromExpr,
+    embed::KurtError,
+    expr::{Block, ERef, Expr, _NIL},
+    Exception, Kurt, Loc,
+};
 
 mod core;
 mod eq;
+mod flow;
 mod math;
 mod str;
 mod list;
 mod dict;
 
 impl Kurt {
-    pub fn init_lib(&mut self) {
-        self.init_core();
-        self.init_math();
-        self.init_str();
-        self.init_list();
-        self.init_dict();
+    pub fn init_lib(&mut self) -> Result<(), KurtError> {
+        self.init_core()?;
+        self.init_flow()?;
+        self.init_math()?;
+        self.init_str()?;
+        self.init_list()?;
+        self.init_dict()?;
+        Ok(())
     }
 
-    pub fn builtin(&self, name: &'static str, args: &Vec<String>) -> Expr {
-        Expr::EBlock(ERef::new(Block {
+    // Creates a native block. Its params are specs as in block params (e.g. "x", "y:nil"), with
+    // defaults for optional args. Throws if a spec is invalid.
+    pub fn builtin(&self, name: &str, args: &[String]) -> Result<Expr, KurtError> {
+        let mut params = Vec::new();
+        for spec in args {
+            match self.parse_param(spec) {
+                Ok(param) => params.push(param),
+                Err(msg) => {
+                    let msg = format!("'{}': {}", name, msg);
+                    return self.throw(&self.root, msg).map_err(KurtError::from);
+                }
+            }
+        }
+        Ok(Expr::EBlock(ERef::new(Block {
             loc: Loc {
                 file: String::default(),
                 name: name.to_string(),
                 pos: (0, 0),
             },
-            params: args.clone(),
-            expr: Expr::ENative(name),
+            params,
+            expr: Expr::ENative(name.to_string()),
             env: _NIL,
             slf: _NIL,
-        }))
+            is_macro: false,
+        })))
     }
 
     pub fn add_builtin(
         &mut self,
         name: &'static str,
-        args: &Vec<String>,
-        f: fn(&Kurt, &Expr) -> Expr,
-    ) {
-        self.builtins.insert(name, f);
-        self.def(
-            &self.root.clone(),
-            &_id(name),
-            &self.builtin(name, args).clone(),
-        );
+        args: &[String],
+        f: fn(&Kurt, &Expr) -> Result<Expr, Exception>,
+    ) -> Result<(), KurtError> {
+        self.add_native(name, args, f)
+    }
+
+    // Registers a native closure and binds it in root, as with add_builtin().
+    // Closures may capture host state, and can be added at any time (including from other natives).
+    // Registering an existing name replaces its implementation. Nothing is registered if a param
+    // spec is invalid.
+    pub fn add_native<F>(&self, name: &str, args: &[String], f: F) -> Result<(), KurtError>
+    where
+        F: Fn(&Kurt, &Expr) -> Result<Expr, Exception> + 'static,
+    {
+        let block = self.builtin(name, args)?;
+        self.builtins
+            .borrow_mut()
+            .insert(name.to_string(), Rc::new(f));
+        if let Expr::EDict(root_ref) = &self.root {
+            root_ref.borrow_mut().map.insert(name.into(), block);
+        }
+        Ok(())
     }
 
-    pub fn loc(&self, env: &Expr, name: &str) -> Expr {
+    pub fn loc(&self, env: &Expr, name: &str) -> Result<Expr, Exception> {
         if let Expr::EDict(env_map_ref) = &env {
             let env_map = &env_map_ref.borrow().map;
             match env_map.get(name) {
-                Some(result) => result.clone(),
+                Some(result) => Ok(result.clone()),
                 None => self.throw(&env, format!("missing local '{}' in {}", name, env)),
             }
         } else {
@@ -56,59 +92,41 @@ impl Kurt {
         }
     }
 
-    pub fn loc_list(&self, env: &Expr, name: &str) -> Vec<Expr> {
-        match &self.loc(env, name) {
-            Expr::EList(list_ref) => {
-                let list = &*list_ref.borrow();
-                list.exprs.clone()
-            }
-            _ => self.throw(env, format!("expected list")),
+    // Gets a local, converted to a Rust value.
+    // Conversion failures are thrown as exceptions naming the local.
+    pub fn loc_as<T: FromExpr>(&self, env: &Expr, name: &str) -> Result<T, Exception> {
+        let expr = self.loc(env, name)?;
+        match T::from_expr(&expr) {
+            Ok(value) => Ok(value),
+            Err(err) => self.throw(env, format!("{}: {}", name, err)),
         }
     }
 
-    pub fn loc_opt(&self, env: &Expr, name: &str) -> Option<Expr> {
+    pub fn loc_list(&self, env: &Expr, name: &str) -> Result<Vec<Expr>, Exception> {
+        self.loc_as(env, name)
+    }
+
+    pub fn loc_opt(&self, env: &Expr, name: &str) -> Result<Option<Expr>, Exception> {
         if let Expr::EDict(env_map_ref) = &env {
             let env_map = &env_map_ref.borrow().map;
             match env_map.get(name) {
-                Some(expr) => Some(expr.clone()),
-                None => None,
+                Some(expr) => Ok(Some(expr.clone())),
+                None => Ok(None),
             }
         } else {
             self.throw(env, format!("expected dict env"))
         }
     }
 
-    pub fn loc_str(&self, env: &Expr, name: &str) -> String {
-        let expr = self.loc(env, name);
-        match &expr {
-            Expr::EStr(s) => s.clone(),
-            _ => self.throw(env, format!("expected str, got {}", expr)),
-        }
-    }
-
-    pub fn loc_num(&self, env: &Expr, name: &str) -> f64 {
-        let expr = self.loc(env, name);
-        match &expr {
-            Expr::ENum(x) => *x,
-            _ => self.throw(env, format!("expected num, got {}", expr)),
-        }
+    pub fn loc_str(&self, env: &Expr, name: &str) -> Result<String, Exception> {
+        self.loc_as(env, name)
     }
 
-    pub fn loc_bool(&self, env: &Expr, name: &str) -> bool {
-        let expr = self.loc(env, name);
-        match &expr {
-            Expr::EBool(x) => *x,
-            _ => self.throw(env, format!("expected bool, got {}", expr)),
-        }
+    pub fn loc_num(&self, env: &Expr, name: &str) -> Result<f64, Exception> {
+        self.loc_as(env, name)
     }
 
-    pub fn loc_opt_num(&self, env: &Expr, name: &str) -> Option<f64> {
-        match self.loc_opt(env, name) {
-            Some(expr) => match &expr {
-                Expr::ENum(x) => Some(*x),
-                _ => self.throw(env, format!("expected num, got {}", expr)),
-            },
-            None => None,
-        }
+    pub fn loc_bool(&self, env: &Expr, name: &str) -> Result<bool, Exception> {
+        self.loc_as(env, name)
     }
 }
@@ -1,31 +1,194 @@
-use velcro::{hash_map, vec_from};
+use velcro::{map_iter, vec_from};
 
 use crate::kurt::{
-    expr::{_dict, _num},
-    Expr,
+    embed::KurtError,
+    expr::{_dict, _list, _num, _str, _NIL},
+    Exception, Expr,
 };
 
 use super::Kurt;
 
+// The longest string (in bytes) that repeat will build. Anything bigger is almost certainly a bug,
+// and failing to allocate it would abort the host rather than throw.
+const MAX_REPEAT_LEN: usize = 1 << 24;
+
 impl Kurt {
-    pub fn init_str(&mut self) {
-        self.add_builtin("str:len", &vec_from![], Kurt::native_str_len);
-        self.def_str = _dict(hash_map!(
-            "len".into(): self.builtin("str:len", &vec_from![]),
-        ));
+    pub fn init_str(&mut self) -> Result<(), KurtError> {
+        self.add_builtin("str:len", &vec_from![], Kurt::native_str_len)?;
+        self.add_builtin("str:concat", &vec_from!["vals..."], Kurt::native_str_concat)?;
+        self.add_builtin("str:slice", &vec_from!["start", "end:nil"], Kurt::native_str_slice)?;
+        self.add_builtin("str:split", &vec_from!["sep:nil"], Kurt::native_str_split)?;
+        self.add_builtin("str:join", &vec_from!["vals"], Kurt::native_str_join)?;
+        self.add_builtin("str:trim", &vec_from![], Kurt::native_str_trim)?;
+        self.add_builtin("str:find", &vec_from!["sub"], Kurt::native_str_find)?;
+        self.add_builtin("str:replace", &vec_from!["from", "to"], Kurt::native_str_replace)?;
+        self.add_builtin("str:starts-with", &vec_from!["prefix"], Kurt::native_str_starts_with)?;
+        self.add_builtin("str:ends-with", &vec_from!["suffix"], Kurt::native_str_ends_with)?;
+        self.add_builtin("str:upper", &vec_from![], Kurt::native_str_upper)?;
+        self.add_builtin("str:lower", &vec_from![], Kurt::native_str_lower)?;
+        self.add_builtin("str:chars", &vec_from![], Kurt::native_str_chars)?;
+        self.add_builtin("str:codes", &vec_from![], Kurt::native_str_codes)?;
+        self.add_builtin("str:repeat", &vec_from!["n"], Kurt::native_str_repeat)?;
+        self.add_builtin("str:num", &vec_from![], Kurt::native_str_num)?;
+        self.def_str = _dict(map_iter!(
+            "len".into(): self.builtin("str:len", &vec_from![])?,
+            "concat".into(): self.builtin("str:concat", &vec_from!["vals..."])?,
+            "slice".into(): self.builtin("str:slice", &vec_from!["start", "end:nil"])?,
+            "split".into(): self.builtin("str:split", &vec_from!["sep:nil"])?,
+            "join".into(): self.builtin("str:join", &vec_from!["vals"])?,
+            "trim".into(): self.builtin("str:trim", &vec_from![])?,
+            "find".into(): self.builtin("str:find", &vec_from!["sub"])?,
+            "replace".into(): self.builtin("str:replace", &vec_from!["from", "to"])?,
+            "starts-with".into(): self.builtin("str:starts-with", &vec_from!["prefix"])?,
+            "ends-with".into(): self.builtin("str:ends-with", &vec_from!["suffix"])?,
+            "upper".into(): self.builtin("str:upper", &vec_from![])?,
+            "lower".into(): self.builtin("str:lower", &vec_from![])?,
+            "chars".into(): self.builtin("str:chars", &vec_from![])?,
+            "codes".into(): self.builtin("str:codes", &vec_from![])?,
+            "repeat".into(): self.builtin("str:repeat", &vec_from!["n"])?,
+            "num".into(): self.builtin("str:num", &vec_from![])?,
+        ).collect());
+        Ok(())
+    }
+
+    // Lengths and indices count chars (Unicode scalar values), not bytes.
+    fn native_str_len(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        Ok(_num(s.chars().count() as f64))
+    }
+
+    // Non-string values are appended as they print, e.g. ("hp: ".concat 10) -> "hp: 10".
+    fn native_str_concat(&self, env: &Expr) -> Result<Expr, Exception> {
+        let mut s = self.loc_str(env, "@")?;
+        for val in self.loc_list(env, "vals")? {
+            s.push_str(&format!("{}", val));
+        }
+        Ok(_str(&s))
+    }
+
+    // (s.slice start end) -> chars [start, end), where end defaults to the end of the string.
+    // Indices are clamped to the string.
+    fn native_str_slice(&self, env: &Expr) -> Result<Expr, Exception> {
+        let chars = self.loc_str(env, "@")?.chars().collect::<Vec<char>>();
+        let start = self.loc_num(env, "start")?.max(0f64) as usize;
+        let end = match self.loc_as::<Option<f64>>(env, "end")? {
+            Some(end) => end.max(0f64) as usize,
+            None => chars.len(),
+        };
+        let end = end.min(chars.len());
+        let start = start.min(end);
+        Ok(_str(&chars[start..end].iter().collect::<String>()))
+    }
+
+    // Splits on sep, or on runs of whitespace if sep is nil.
+    fn native_str_split(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        let parts: Vec<Expr> = match self.loc_as::<Option<String>>(env, "sep")? {
+            Some(sep) if sep.is_empty() => return self.throw(env, "split separator must not be empty".into()),
+            Some(sep) => s.split(sep.as_str()).map(_str).collect(),
+            None => s.split_whitespace().map(_str).collect(),
+        };
+        Ok(_list(parts))
+    }
+
+    // (sep.join vals) joins a list's values with sep between them, formatting them as concat does.
+    fn native_str_join(&self, env: &Expr) -> Result<Expr, Exception> {
+        let sep = self.loc_str(env, "@")?;
+        let vals = self.loc_list(env, "vals")?;
+        let parts = vals.iter().map(|val| format!("{}", val)).collect::<Vec<String>>();
+        Ok(_str(&parts.join(sep.as_str())))
+    }
+
+    fn native_str_trim(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        Ok(_str(s.trim()))
+    }
+
+    // The char index of the first occurrence of sub, or nil.
+    fn native_str_find(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        let sub = self.loc_str(env, "sub")?;
+        match s.find(sub.as_str()) {
+            Some(byte_pos) => Ok(_num(s[..byte_pos].chars().count() as f64)),
+            None => Ok(_NIL),
+        }
+    }
+
+    // Replaces all occurrences of from.
+    fn native_str_replace(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        let from = self.loc_str(env, "from")?;
+        let to = self.loc_str(env, "to")?;
+        if from.is_empty() {
+            return self.throw(env, "replace requires a non-empty string to replace".into());
+        }
+        Ok(_str(&s.replace(from.as_str(), to.as_str())))
+    }
+
+    fn native_str_starts_with(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        let prefix = self.loc_str(env, "prefix")?;
+        Ok(Expr::EBool(s.starts_with(prefix.as_str())))
+    }
+
+    fn native_str_ends_with(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        let suffix = self.loc_str(env, "suffix")?;
+        Ok(Expr::EBool(s.ends_with(suffix.as_str())))
+    }
+
+    fn native_str_upper(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        Ok(_str(&s.to_uppercase()))
+    }
+
+    fn native_str_lower(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        Ok(_str(&s.to_lowercase()))
+    }
+
+    // A list of single-char strings.
+    fn native_str_chars(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        Ok(_list(s.chars().map(|c| _str(&c.to_string())).collect()))
+    }
+
+    // A list of the string's code points, as numbers.
+    fn native_str_codes(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        Ok(_list(s.chars().map(|c| _num(c as u32 as f64)).collect()))
+    }
+
+    fn native_str_repeat(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        let n = self.loc_num(env, "n")?;
+        if n < 0f64 || n.fract() != 0f64 {
+            return self.throw(env, format!("repeat requires a whole, non-negative count, got {}", n));
+        }
+        // Check the size up front, as String::repeat() panics if it overflows (and n as usize
+        // saturates).
+        match s.len().checked_mul(n as usize) {
+            Some(len) if n < usize::MAX as f64 && len <= MAX_REPEAT_LEN => Ok(_str(&s.repeat(n as usize))),
+            _ => self.throw(env, format!("repeat count {} is too large", n)),
+        }
     }
 
-    fn native_str_len(&self, env: &Expr) -> Expr {
-        let s = self.loc_str(&env, "@");
-        _num(s.len() as f64)
+    // Parses the (trimmed) string as a number, or yields nil if it isn't one.
+    fn native_str_num(&self, env: &Expr) -> Result<Expr, Exception> {
+        let s = self.loc_str(env, "@")?;
+        match s.trim().parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(_num(n)),
+            _ => Ok(_NIL),
+        }
     }
 }
 
+#[cfg(test)]
 mod tests {
     use crate::kurt::Kurt;
 
     #[test]
-    fn math() {
+    fn str() {
         Kurt::test_file("src/kurt/lib/str_test.kurt");
     }
 }
@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+// Standard library sources, embedded at compile time.
+pub const STDLIB: &[(&str, &str)] = &[("std/core.kurt", include_str!("lib/core.kurt"))];
+
+// Loads Kurt source for imports and files.
+// Paths are first resolved to a key that uniquely identifies a source (and is used to cache
+// modules and report locations), then the source for that key is loaded.
+pub trait Loader {
+    // Resolves a path, as imported from the source with key `from` (if any).
+    // Paths starting with ./ or ../ are relative to `from`. The .kurt extension is optional.
+    fn resolve(&self, from: Option<&str>, path: &str) -> Option<String>;
+
+    // Loads the source for a key returned by resolve().
+    fn load(&self, key: &str) -> Result<String, String>;
+}
+
+// Loads sources from the filesystem, resolving non-relative paths against a list of roots.
+// Keys are canonical file paths.
+pub struct FsLoader {
+    pub roots: Vec<PathBuf>,
+}
+
+impl FsLoader {
+    pub fn new(roots: &[&str]) -> FsLoader {
+        FsLoader {
+            roots: roots.iter().map(PathBuf::from).collect(),
+        }
+    }
+}
+
+impl Loader for FsLoader {
+    fn resolve(&self, from: Option<&str>, path: &str) -> Option<String> {
+        let dirs = if is_relative(path) {
+            match from.and_then(|from| Path::new(from).parent()) {
+                Some(dir) => vec![dir.to_path_buf()],
+                None => vec![PathBuf::from(".")],
+            }
+        } else {
+            self.roots.clone()
+        };
+
+        for dir in dirs {
+            for candidate in &[dir.join(path), dir.join(format!("{}.kurt", path))] {
+                if candidate.is_file() {
+                    return fs::canonicalize(candidate)
+                        .ok()
+                        .map(|file| file.to_string_lossy().to_string());
+                }
+            }
+        }
+        None
+    }
+
+    fn load(&self, key: &str) -> Result<String, String> {
+        fs::read_to_string(key).map_err(|err| err.to_string())
+    }
+}
+
+// Loads sources from an in-memory map of virtual paths (e.g. "lib/util.kurt") to source.
+#[derive(Default)]
+pub struct MemLoader {
+    pub files: HashMap<String, String>,
+}
+
+impl MemLoader {
+    pub fn new(files: HashMap<String, String>) -> MemLoader {
+        MemLoader { files }
+    }
+}
+
+impl Loader for MemLoader {
+    fn resolve(&self, from: Option<&str>, path: &str) -> Option<String> {
+        resolve_virtual(from, path, |key| self.files.contains_key(key))
+    }
+
+    fn load(&self, key: &str) -> Result<String, String> {
+        match self.files.get(key) {
+            Some(src) => Ok(src.clone()),
+            None => Err(format!("'{}' not found", key)),
+        }
+    }
+}
+
+// Loads sources embedded at compile time, e.g. with include_str!().
+pub struct EmbeddedLoader {
+    pub files: &'static [(&'static str, &'static str)],
+}
+
+impl EmbeddedLoader {
+    pub fn new(files: &'static [(&'static str, &'static str)]) -> EmbeddedLoader {
+        EmbeddedLoader { files }
+    }
+
+    fn get(&self, key: &str) -> Option<&'static str> {
+        self.files
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, src)| *src)
+    }
+}
+
+impl Loader for EmbeddedLoader {
+    fn resolve(&self, from: Option<&str>, path: &str) -> Option<String> {
+        resolve_virtual(from, path, |key| self.get(key).is_some())
+    }
+
+    fn load(&self, key: &str) -> Result<String, String> {
+        match self.get(key) {
+            Some(src) => Ok(src.to_string()),
+            None => Err(format!("'{}' not found", key)),
+        }
+    }
+}
+
+// Tries each loader in turn; the first to resolve a path wins.
+pub struct ChainLoader {
+    pub loaders: Vec<Box<dyn Loader>>,
+}
+
+impl ChainLoader {
+    pub fn new(loaders: Vec<Box<dyn Loader>>) -> ChainLoader {
+        ChainLoader { loaders }
+    }
+}
+
+impl Loader for ChainLoader {
+    fn resolve(&self, from: Option<&str>, path: &str) -> Option<String> {
+        self.loaders
+            .iter()
+            .find_map(|loader| loader.resolve(from, path))
+    }
+
+    // Loads with the loader that resolves the key (to itself), so that its error is the one reported.
+    fn load(&self, key: &str) -> Result<String, String> {
+        match self
+            .loaders
+            .iter()
+            .find(|loader| loader.resolve(None, key).as_deref() == Some(key))
+        {
+            Some(loader) => loader.load(key),
+            None => Err(format!("'{}' not found", key)),
+        }
+    }
+}
+
+fn is_relative(path: &str) -> bool {
+    path.starts_with("./") || path.starts_with("../")
+}
+
+// Resolves a path against a set of virtual keys, normalizing . and .. components.
+fn resolve_virtual<F>(from: Option<&str>, path: &str, exists: F) -> Option<String>
+where
+    F: Fn(&str) -> bool,
+{
+    let joined = match from.and_then(|from| Path::new(from).parent()) {
+        Some(dir) if is_relative(path) => dir.join(path),
+        _ => PathBuf::from(path),
+    };
+
+    let mut parts = Vec::<String>::new();
+    for component in joined.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            _ => (),
+        }
+    }
+
+    let key = parts.join("/");
+    [key.clone(), format!("{}.kurt", key)]
+        .iter()
+        .find(|candidate| exists(candidate))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use velcro::hash_map;
+
+    use crate::kurt::{
+        expr::{_num, _TRUE},
+        Kurt,
+    };
+
+    use super::{ChainLoader, Loader, MemLoader};
+
+    #[test]
+    fn mem_loader() {
+        let kurt = Kurt::with_loader(Box::new(MemLoader::new(hash_map! {
+            "game/main.kurt".into(): r#"
+                (def :util (import "./lib/util"))
+                (def :result (+ util.answer (util.twice 1)))
+            "#.into(),
+            "game/lib/util.kurt".into(): r#"
+                (def :answer 40)
+                (def :twice (x | * x 2))
+            "#.into(),
+        })));
+
+        // The embedded stdlib is still available alongside the in-memory sources.
+        assert!(kurt.eval_src(&kurt.root, "test", "(? :print-exception)") == _TRUE);
+        assert!(kurt.eval_src(&kurt.root, "test", "(import \"game/main\").result") == _num(42f64));
+    }
+
+    // Resolves every path, but can't load any of them.
+    struct BrokenLoader;
+
+    impl Loader for BrokenLoader {
+        fn resolve(&self, _from: Option<&str>, path: &str) -> Option<String> {
+            Some(path.to_string())
+        }
+
+        fn load(&self, _key: &str) -> Result<String, String> {
+            Err("disk on fire".to_string())
+        }
+    }
+
+    #[test]
+    fn chain_loader_errors() {
+        // The error comes from the loader that resolved the key, not the last one tried.
+        let chain = ChainLoader::new(vec![Box::new(BrokenLoader), Box::new(MemLoader::default())]);
+        let key = chain.resolve(None, "main.kurt").unwrap();
+        assert_eq!(Err("disk on fire".to_string()), chain.load(key.as_str()));
+
+        let chain = ChainLoader::new(vec![Box::new(MemLoader::default())]);
+        assert_eq!(Err("'main.kurt' not found".to_string()), chain.load("main.kurt"));
+    }
+}
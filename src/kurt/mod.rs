@@ -1,19 +1,30 @@
+use std::any::TypeId;
+use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::panic::{RefUnwindSafe, UnwindSafe};
-use std::{fs, panic};
+use std::hash::Hash;
+use std::rc::Rc;
 
 use gc::Finalize;
 use gc::Trace;
-use velcro::hash_map;
+use velcro::map_iter;
 
 use crate::kurt::expr::{_id, _list, _num, _str};
 
-use self::expr::{ERef, Expr, _dict, _NIL};
+use self::expr::{ERef, Expr, Key, _dict, _NIL};
+use self::loader::{ChainLoader, EmbeddedLoader, FsLoader, Loader, STDLIB};
+use self::object::HostType;
+use self::ordered_map::OrderedMap;
 
 pub mod apply;
+pub mod convert;
+pub mod embed;
 pub mod eval;
 pub mod expr;
+pub mod loader;
+pub mod module;
+pub mod object;
+pub mod ordered_map;
 pub mod parse;
 pub mod print;
 
@@ -23,92 +34,164 @@ pub struct Kurt {
     pub root: Expr,
     debug: bool,
 
-    builtins: HashMap<&'static str, fn(&Kurt, &Expr) -> Expr>,
+    builtins: RefCell<HashMap<String, Native>>,
     def_num: Expr,
     def_str: Expr,
     def_list: Expr,
     def_dict: Expr,
 
-    exception: RefCell<Option<Expr>>,
+    host_types: RefCell<HashMap<TypeId, HostType>>,
+
+    // Apply left pending by a native's apply_tail().
+    tail: RefCell<Option<(Expr, Vec<Expr>)>>,
+
+    loader: ChainLoader,
+    modules: RefCell<HashMap<String, Expr>>,
+    loading: RefCell<Vec<String>>,
 }
 
 #[derive(Debug, Default, Trace, Finalize, PartialEq, Clone)]
 pub struct Loc {
-    file: String,
-    name: String,
-    pos: (usize, usize),
+    pub file: String,
+    pub name: String,
+    pub pos: (usize, usize),
 }
 
-// Needed for the use of kurt refs in the panic handler.
-impl UnwindSafe for Kurt {}
-impl RefUnwindSafe for Kurt {}
+// A native function, invoked with the frame holding its args.
+pub type Native = Rc<dyn Fn(&Kurt, &Expr) -> Result<Expr, Exception>>;
+
+// Unwinds evaluation, propagated as the Err side of eval, apply, and native results.
+// This is either a thrown exception, or a control signal on its way to the loop that handles it.
+// Only thrown exceptions can be caught by (try ...).
+#[derive(Clone)]
+pub enum Exception {
+    // A thrown exception dict: { :message :stack ... }
+    Throw(Expr),
+    // (break value) -- exits the innermost enclosing loop.
+    Break(Expr),
+    // (continue) -- skips to the innermost enclosing loop's next iteration.
+    Continue,
+    // (return value) -- completes the given block frame with a value.
+    Return(Expr, Expr),
+}
 
 impl Kurt {
     pub fn new() -> Kurt {
+        Kurt::with_loader(Box::new(FsLoader::new(&["."])))
+    }
+
+    // Creates an interpreter that loads sources with the given loader.
+    // The embedded standard library is always available, ahead of the given loader.
+    pub fn with_loader(loader: Box<dyn Loader>) -> Kurt {
         let mut kurt = Kurt {
-            builtins: HashMap::new(),
-            root: _dict(HashMap::new()),
+            builtins: RefCell::new(HashMap::new()),
+            root: _dict(OrderedMap::new()),
             def_num: _NIL,
             def_str: _NIL,
             def_dict: _NIL,
             def_list: _NIL,
             debug: false,
-            exception: RefCell::new(None),
+            host_types: RefCell::new(HashMap::new()),
+            tail: RefCell::new(None),
+            loader: ChainLoader::new(vec![Box::new(EmbeddedLoader::new(STDLIB)), loader]),
+            modules: RefCell::new(HashMap::new()),
+            loading: RefCell::new(Vec::new()),
         };
-        kurt.init_lib();
+        // The stdlib is embedded and its param specs are fixed, so failing to set it up is a bug
+        // rather than a Kurt error.
+        if let Err(e) = kurt.init_lib() {
+            panic!("cannot init stdlib: {}", e);
+        }
         kurt
     }
 
+    // Runs a test file, panicking on the first uncaught exception (e.g. a failed expect), so that
+    // it fails the calling test rather than just being printed.
     pub fn test_file(filename: &str) {
         println!("-- {}", filename);
         let kurt = Kurt::new();
-        kurt.eval_file(filename);
+        if let Err(e) = kurt.run_file(filename) {
+            panic!("{}", e);
+        }
     }
 
     pub fn eval_src(&self, env: &Expr, name: &str, src: &str) -> Expr {
-        match panic::catch_unwind(|| {
-            let expr = self.parse(name.into(), src.into());
-            self.eval(env, &expr)
-        }) {
+        match self.eval_forms(env, name, src) {
             Ok(expr) => expr,
-            Err(_) => match self.exception.replace(None) {
-                Some(expr) => {
-                    self.apply(&self.root, vec![_id("print-exception"), expr]);
-                    _NIL
+            Err(Exception::Throw(e)) => {
+                if let Err(Exception::Throw(pe)) =
+                    self.apply(&self.root, vec![_id("print-exception"), e.clone()])
+                {
+                    // Don't lose the original if print-exception itself fails.
+                    println!("{}\n(in print-exception: {})", e, pe);
                 }
-                None => _NIL,
-            },
+                _NIL
+            }
+            // eval_forms() turns escaped control signals into exceptions.
+            Err(_) => unreachable!(),
         }
     }
 
-    pub fn eval_file(&self, filename: &str) {
-        self.eval_src(
-            &self.root,
-            filename,
-            fs::read_to_string(filename)
-                .expect("cannot read test file")
-                .as_str(),
-        );
+    // Evaluates top-level forms in order, yielding the last value.
+    // Unlike eval_src(), exceptions propagate to the caller.
+    pub fn eval_forms(&self, env: &Expr, name: &str, src: &str) -> Result<Expr, Exception> {
+        let exprs = self.parse(env, name, src)?;
+        let mut last = _NIL;
+        for expr in &exprs {
+            last = self.uncaught(env, self.eval(env, expr))?;
+        }
+        Ok(last)
+    }
+
+    // Loads and evaluates a file in root, printing any uncaught exception as eval_src() does.
+    // Only a file that can't be loaded is returned as an Err.
+    pub fn eval_file(&self, filename: &str) -> Result<Expr, Exception> {
+        let (key, src) = match self.load_file(filename) {
+            Ok(file) => file,
+            Err(msg) => return self.throw(&self.root, msg),
+        };
+
+        // Track the file so that it can import modules relative to itself.
+        self.loading.borrow_mut().push(key);
+        let result = self.eval_src(&self.root, filename, src.as_str());
+        self.loading.borrow_mut().pop();
+        Ok(result)
+    }
+
+    // Resolves and loads a top-level file via the loader, returning its key and source.
+    fn load_file(&self, filename: &str) -> Result<(String, String), String> {
+        let key = match self.loader.resolve(None, filename) {
+            Some(key) => key,
+            None => return Err(format!("cannot find file '{}'", filename)),
+        };
+        let src = self.loader.load(key.as_str())?;
+        Ok((key, src))
     }
 
-    pub fn def(&self, env: &Expr, key: &Expr, val: &Expr) {
-        match (env, key) {
-            (Expr::EDict(dict_ref), Expr::EId(name)) => {
+    pub fn def(&self, env: &Expr, key: &Expr, val: &Expr) -> Result<(), Exception> {
+        match (env, Key::from_expr(key)) {
+            (Expr::EDict(dict_ref), Some(key)) => {
                 let map = &mut dict_ref.borrow_mut().map;
-                map.insert(name.clone(), val.clone());
+                map.insert(key, val.clone());
+                Ok(())
             }
-            _ => self.throw(env, "def requires dict :id".to_string()),
+            _ => self.throw(env, format!("def requires dict key, got {:#}", key)),
         }
     }
 
-    pub fn set(&self, env: &Expr, name: &Expr, val: &Expr) {
+    pub fn set(&self, env: &Expr, name: &Expr, val: &Expr) -> Result<(), Exception> {
         match (env, name) {
-            (Expr::EDict(_), Expr::EId(s)) => {
-                let target = self.find_scope(env, s);
+            (Expr::EDict(_), _) => {
+                let key = match Key::from_expr(name) {
+                    Some(key) => key,
+                    None => return self.throw(env, format!("set requires dict key, got {:#}", name)),
+                };
+                let target = self.find_scope(env, &key);
                 match &target {
                     Some(Expr::EDict(dict_ref)) => {
                         let map = &mut dict_ref.borrow_mut().map;
-                        map.insert(s.clone(), val.clone());
+                        map.insert(key, val.clone());
+                        Ok(())
                     }
                     _ => self.throw(env, format!("{} not found", name)),
                 }
@@ -116,31 +199,37 @@ impl Kurt {
 
             (Expr::EList(list_ref), Expr::ENum(idx)) => {
                 let list = &mut *list_ref.borrow_mut();
-                let expr = list.exprs.get_mut(idx.floor() as usize).unwrap();
-                *expr = val.clone();
+                let len = list.exprs.len();
+                match list.exprs.get_mut(idx.floor() as usize) {
+                    Some(expr) => {
+                        *expr = val.clone();
+                        Ok(())
+                    }
+                    None => self.throw(env, format!("index {} out of bounds ({})", idx, len)),
+                }
             }
 
             (_, _) => self.throw(
                 env,
                 format!(
-                    "set requires (dict id) or (list num); got ({} {})",
+                    "set requires (dict key) or (list num); got ({} {})",
                     env, name
                 ),
             ),
         }
     }
 
-    pub fn get(&self, env: &Expr, name: &Expr) -> Expr {
+    pub fn get(&self, env: &Expr, name: &Expr) -> Result<Expr, Exception> {
         match (env, &name) {
             (Expr::EDict(_), Expr::EId(name)) => {
                 match name.as_str() {
                     // Special case: env refers to the current environment.
-                    "env" => env.clone(),
+                    "env" => Ok(env.clone()),
                     _ => {
                         let target = self.find_scope(env, name);
                         match &target {
                             Some(Expr::EDict(dict_ref)) => {
-                                dict_ref.borrow().map.get(name).unwrap().clone()
+                                Ok(dict_ref.borrow().map.get(name).unwrap().clone())
                             }
                             _ => self.throw(env, format!("'{}' not found", name)),
                         }
@@ -149,24 +238,36 @@ impl Kurt {
             }
 
             (Expr::EList(list_ref), Expr::ENum(x)) => {
-                // TODO: bounds/error checking
-                list_ref
-                    .borrow()
-                    .exprs
-                    .get(x.floor() as usize)
-                    .unwrap()
-                    .clone()
+                let list = &*list_ref.borrow();
+                match list.exprs.get(x.floor() as usize) {
+                    Some(expr) => Ok(expr.clone()),
+                    None => self.throw(
+                        env,
+                        format!("index {} out of bounds ({})", x, list.exprs.len()),
+                    ),
+                }
             }
 
             (Expr::EList(_), Expr::EId(name)) => self.get(&self.def_list, &_id(name)),
             (Expr::ENum(_), Expr::EId(name)) => self.get(&self.def_num, &_id(name)),
             (Expr::EStr(_), Expr::EId(name)) => self.get(&self.def_str, &_id(name)),
+            (Expr::EObject(obj_ref), Expr::EId(name)) => {
+                let obj = &*obj_ref.borrow();
+                match self.host_methods(obj) {
+                    Expr::ENil => self.throw(env, format!("'{}' not found on <{}>", name, obj.name)),
+                    methods => self.get(&methods, &_id(name)),
+                }
+            }
 
-            (_, _) => name.clone(),
+            (_, _) => Ok(name.clone()),
         }
     }
 
-    pub fn find_scope(&self, target: &Expr, name: &String) -> Option<Expr> {
+    // Finds the dict that defines name, searching parents and then the dict builtins.
+    pub fn find_scope<Q: Eq + Hash + ?Sized>(&self, target: &Expr, name: &Q) -> Option<Expr>
+    where
+        Key: Borrow<Q>,
+    {
         match target {
             // Check current dict.
             Expr::EDict(dict_ref) => {
@@ -175,7 +276,7 @@ impl Kurt {
                 }
 
                 // Check parent.
-                match dict_ref.borrow().map.get("^") {
+                match dict_ref.borrow().map.get::<str>("^") {
                     Some(next) => self.find_scope(next, name),
                     None => match &self.def_dict {
                         Expr::EDict(def_map_ref) => {
@@ -194,27 +295,66 @@ impl Kurt {
         }
     }
 
-    pub fn throw(&self, env: &Expr, msg: String) -> ! {
+    // Looks up a key in a dict and its parents. Unlike find_scope(), this doesn't fall back to the
+    // dict methods, so it's used for reading a dict's data (e.g. destructuring).
+    pub fn dict_entry<Q: Eq + Hash + ?Sized>(&self, dict: &Expr, key: &Q) -> Option<Expr>
+    where
+        Key: Borrow<Q>,
+    {
+        let mut cur = dict.clone();
+        loop {
+            let next = match &cur {
+                Expr::EDict(dict_ref) => {
+                    let dict = dict_ref.borrow();
+                    if let Some(value) = dict.map.get(key) {
+                        return Some(value.clone());
+                    }
+                    dict.map.get::<str>("^")?.clone()
+                }
+                _ => return None,
+            };
+            cur = next;
+        }
+    }
+
+    // Creates an exception with the given message, as an Err to be returned.
+    pub fn throw<T>(&self, env: &Expr, msg: String) -> Result<T, Exception> {
+        let mut map = OrderedMap::<Key, Expr>::new();
+        map.insert("message".into(), _str(msg.as_str()));
+        self.throw_map(env, map)
+    }
+
+    // Creates an exception dict with the given fields, adding the stack for env.
+    pub fn throw_map<T>(&self, env: &Expr, mut map: OrderedMap<Key, Expr>) -> Result<T, Exception> {
         // TODO: There's gotta be a way to make this less shitty.
-        let mut map = HashMap::<String, Expr>::new();
-        map.insert("message".to_string(), _str(msg.as_str()));
         let mut stack = Vec::<Expr>::new();
 
         let mut cur = Some(env.clone());
         while let Some(expr) = cur {
             match expr.loc() {
-                Some(loc) => stack.push(_dict(hash_map! {
+                Some(loc) => stack.push(_dict(map_iter! {
                     "file".into(): _str(loc.file.as_str()),
                     "name".into(): _str(loc.name.as_str()),
                     "pos".into(): _list(vec![_num(loc.pos.0 as f64), _num(loc.pos.1 as f64)]),
-                })),
+                }.collect())),
                 None => (),
             }
             cur = expr.caller();
         }
 
-        map.insert("stack".to_string(), _list(stack));
-        self.exception.replace(Some(_dict(map)));
-        panic!("[exception]")
+        map.insert("stack".into(), _list(stack));
+        Err(Exception::Throw(_dict(map)))
+    }
+
+    // Turns control signals that escaped all enclosing loops into exceptions.
+    pub fn uncaught(&self, env: &Expr, result: Result<Expr, Exception>) -> Result<Expr, Exception> {
+        match result {
+            Err(Exception::Break(_)) => self.throw(env, "break outside of a loop".into()),
+            Err(Exception::Continue) => self.throw(env, "continue outside of a loop".into()),
+            Err(Exception::Return(_, _)) => {
+                self.throw(env, "return from a block that is no longer running".into())
+            }
+            _ => result,
+        }
     }
 }
@@ -0,0 +1,93 @@
+use velcro::map_iter;
+
+use super::{
+    expr::{Dict, ERef, Expr, _loc},
+    loader::{FsLoader, Loader},
+    Exception, Kurt,
+};
+
+impl Kurt {
+    // Adds a root directory against which non-relative module paths are resolved.
+    // It's searched after the interpreter's own loader (and the embedded stdlib).
+    pub fn add_import_path(&mut self, path: &str) {
+        self.loader.loaders.push(Box::new(FsLoader::new(&[path])));
+    }
+
+    // Imports the module at the given path, returning its dict env.
+    // Paths are resolved by the loader, relative to the module currently being loaded.
+    // Each module is evaluated only once, into its own env whose parent is root;
+    // subsequent imports of the same source return the cached dict.
+    pub fn import(&self, env: &Expr, path: &str) -> Result<Expr, Exception> {
+        let from = self.loading.borrow().last().cloned();
+        let key = match self.loader.resolve(from.as_deref(), path) {
+            Some(key) => key,
+            None => return self.throw(env, format!("module '{}' not found", path)),
+        };
+
+        if let Some(module) = self.modules.borrow().get(&key) {
+            return Ok(module.clone());
+        }
+
+        if self.loading.borrow().contains(&key) {
+            let mut chain: Vec<String> = self
+                .loading
+                .borrow()
+                .iter()
+                .skip_while(|loading| **loading != key)
+                .cloned()
+                .collect();
+            chain.push(key.clone());
+            return self.throw(env, format!("import cycle: {}", chain.join(" -> ")));
+        }
+
+        let src = match self.loader.load(key.as_str()) {
+            Ok(src) => src,
+            Err(err) => return self.throw(env, format!("cannot read module '{}': {}", key, err)),
+        };
+
+        let module = Expr::EDict(ERef::new(Dict {
+            loc: _loc(key.as_str(), "", (0, 0)),
+            map: map_iter! {
+                "^".into(): self.root.clone(),
+            }.collect(),
+        }));
+
+        // Pop the loading stack even if evaluation throws, so the module can be retried.
+        self.loading.borrow_mut().push(key.clone());
+        let result = self.eval_forms(&module, key.as_str(), src.as_str());
+        self.loading.borrow_mut().pop();
+        result?;
+
+        self.modules.borrow_mut().insert(key, module.clone());
+        Ok(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kurt::{embed::KurtError, expr::_num, Kurt};
+
+    #[test]
+    fn module() {
+        Kurt::test_file("src/kurt/module_test.kurt");
+    }
+
+    #[test]
+    fn import_path() {
+        let mut kurt = Kurt::new();
+        assert!(kurt.run(&kurt.root, "test", "(import \"shapes\")").is_err());
+
+        kurt.add_import_path("src/kurt/module_test");
+        let square = kurt.run(&kurt.root, "test", "(def :loads 0) ((import \"shapes\").square 3)");
+        assert!(square.unwrap() == _num(9f64));
+    }
+
+    #[test]
+    fn missing_file() {
+        let kurt = Kurt::new();
+        let err = KurtError::from(kurt.eval_file("no/such/file.kurt").err().unwrap());
+        assert_eq!("cannot find file 'no/such/file.kurt'", err.message);
+        let err = kurt.run_file("no/such/file.kurt").err().unwrap();
+        assert_eq!("cannot find file 'no/such/file.kurt'", err.message);
+    }
+}
@@ -0,0 +1,204 @@
+use std::any::{type_name, Any, TypeId};
+
+use gc::{Finalize, Trace};
+
+use super::{
+    expr::{ERef, Expr, Ref, RefMut, _NIL},
+    Exception, Kurt,
+};
+
+// Host (Rust) data that can be carried by an Expr::EObject.
+// Data must be traceable, so that any Exprs it holds are kept alive. Plain data with no Exprs
+// can use #[derive(Trace, Finalize)] with #[unsafe_ignore_trace] on foreign fields.
+pub trait HostData: Trace + Any {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Trace + Any> HostData for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// An opaque host object, tagged with its type's display name.
+// Scripts can only interact with it through its type's method table (see def_host_type()).
+#[derive(Trace, Finalize)]
+pub struct Object {
+    pub name: String,
+    pub data: Box<dyn HostData>,
+}
+
+// Objects have reference identity.
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// A registered host type's display name and methods.
+pub struct HostType {
+    pub name: String,
+    pub methods: Expr,
+}
+
+impl Expr {
+    // Borrows an object's data, if it is of type T.
+    pub fn downcast_ref<T: HostData>(&self) -> Option<Ref<'_, T>> {
+        match self {
+            Expr::EObject(obj_ref) => {
+                let obj = obj_ref.borrow();
+                if (*obj.data).as_any().is::<T>() {
+                    Some(Ref::map(obj, |obj| {
+                        (*obj.data).as_any().downcast_ref::<T>().unwrap()
+                    }))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // Mutably borrows an object's data, if it is of type T.
+    pub fn downcast_mut<T: HostData>(&self) -> Option<RefMut<'_, Object, T>> {
+        match self {
+            Expr::EObject(obj_ref) => {
+                let obj = obj_ref.borrow_mut();
+                if (*obj.data).as_any().is::<T>() {
+                    Some(RefMut::map(obj, |obj| {
+                        (*obj.data).as_any_mut().downcast_mut::<T>().unwrap()
+                    }))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Kurt {
+    // Registers a host type's display name and method table.
+    // Methods are looked up on objects of type T (e.g. (obj.method args...)), with @ bound to the
+    // object, as with the list and str method tables.
+    pub fn def_host_type<T: HostData>(&self, name: &str, methods: Expr) {
+        self.host_types.borrow_mut().insert(
+            TypeId::of::<T>(),
+            HostType {
+                name: name.to_string(),
+                methods,
+            },
+        );
+    }
+
+    // Wraps host data in an object.
+    // Its display name is taken from def_host_type(), falling back to the Rust type name.
+    pub fn object<T: HostData>(&self, data: T) -> Expr {
+        Expr::EObject(ERef::new(Object {
+            name: self.host_type_name::<T>(),
+            data: Box::new(data),
+        }))
+    }
+
+    // Gets the method table for an object's type, or nil if none is registered.
+    pub fn host_methods(&self, obj: &Object) -> Expr {
+        match self.host_types.borrow().get(&(*obj.data).as_any().type_id()) {
+            Some(host_type) => host_type.methods.clone(),
+            None => _NIL,
+        }
+    }
+
+    // Gets a copy of the host data in a local, e.g. a handle passed as an argument.
+    pub fn loc_object<T: HostData + Clone>(&self, env: &Expr, name: &str) -> Result<T, Exception> {
+        let expr = self.loc(env, name)?;
+        let data = expr.downcast_ref::<T>().map(|data| data.clone());
+        match data {
+            Some(data) => Ok(data),
+            None => self.throw(
+                env,
+                format!("{}: expected {}, got {:#}", name, self.host_type_name::<T>(), expr),
+            ),
+        }
+    }
+
+    fn host_type_name<T: HostData>(&self) -> String {
+        match self.host_types.borrow().get(&TypeId::of::<T>()) {
+            Some(host_type) => host_type.name.clone(),
+            None => type_name::<T>().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gc::{Finalize, Trace};
+    use velcro::{map_iter, vec_from};
+
+    use crate::kurt::{
+        expr::{_bool, _dict, _num, _str},
+        Kurt,
+    };
+
+    #[derive(Trace, Finalize, Clone, Debug, PartialEq)]
+    struct Entity(u32);
+
+    #[derive(Trace, Finalize)]
+    struct Counter {
+        count: f64,
+    }
+
+    #[test]
+    fn objects() {
+        let kurt = Kurt::new();
+        kurt.add_native("counter:bump", &vec_from!["by"], |kurt, env| {
+            let this = kurt.loc(env, "@")?;
+            let mut counter = this.downcast_mut::<Counter>().unwrap();
+            counter.count += kurt.loc_num(env, "by")?;
+            Ok(_num(counter.count))
+        })
+        .unwrap();
+        kurt.def_host_type::<Counter>(
+            "Counter",
+            _dict(map_iter! {
+                "bump".into(): kurt.builtin("counter:bump", &vec_from!["by"]).unwrap(),
+            }.collect()),
+        );
+        kurt.add_native("entity-id", &vec_from!["e"], |kurt, env| {
+            Ok(_num(kurt.loc_object::<Entity>(env, "e")?.0 as f64))
+        })
+        .unwrap();
+
+        // Host data round-trips intact, and can be downcast to its type.
+        let counter = kurt.object(Counter { count: 0f64 });
+        let entity = kurt.object(Entity(7));
+        kurt.set_global("counter", counter.clone());
+        kurt.set_global("entity", entity.clone());
+        assert!(kurt.get_global("entity").unwrap() == entity);
+        assert_eq!(Some(Entity(7)), entity.downcast_ref::<Entity>().map(|e| e.clone()));
+        assert!(entity.downcast_ref::<Counter>().is_none());
+        assert!(_str("x").downcast_ref::<Entity>().is_none());
+
+        // Registered types get their display name and methods.
+        assert_eq!("<Counter>", format!("{}", counter));
+        assert!(entity.to_string().ends_with("Entity>"));
+        let result = kurt.run(&kurt.root, "objects", "(counter.bump 2) (counter.bump 3)");
+        assert!(result.unwrap() == _num(5f64));
+        assert_eq!(5f64, counter.downcast_ref::<Counter>().unwrap().count);
+        assert!(kurt.run(&kurt.root, "objects", "(entity-id entity)").unwrap() == _num(7f64));
+
+        // Objects are only equal to themselves.
+        assert!(kurt.run(&kurt.root, "objects", "(= entity entity)").unwrap() == _bool(true));
+        kurt.set_global("other", kurt.object(Entity(7)));
+        assert!(kurt.run(&kurt.root, "objects", "(= entity other)").unwrap() == _bool(false));
+
+        let err = kurt.run(&kurt.root, "objects", "(entity-id counter)").err().unwrap();
+        assert!(err.message.starts_with("e: expected"));
+        let err = kurt.run(&kurt.root, "objects", "(entity.foo)").err().unwrap();
+        assert!(err.message.starts_with("'foo' not found on <"));
+    }
+}
@@ -0,0 +1,195 @@
+use std::{borrow::Borrow, collections::HashMap, hash::Hash, iter::FromIterator, ops::Index, slice, vec};
+
+use gc::{Finalize, Trace};
+
+// A map that remembers the order in which its keys were first inserted, used for dicts so that
+// printing and iterating them is deterministic.
+// Lookups go through a hash index; removal is O(n), as later entries are shifted down.
+#[derive(Trace, Finalize, Clone, Debug)]
+pub struct OrderedMap<K: Eq + Hash, V> {
+    entries: Vec<(K, V)>,
+    index: HashMap<K, usize>,
+}
+
+impl<K: Eq + Hash + Clone, V> OrderedMap<K, V> {
+    pub fn new() -> Self {
+        OrderedMap {
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get<Q: Eq + Hash + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn get_mut<Q: Eq + Hash + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        match self.index.get(key) {
+            Some(&i) => Some(&mut self.entries[i].1),
+            None => None,
+        }
+    }
+
+    pub fn contains_key<Q: Eq + Hash + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.index.contains_key(key)
+    }
+
+    // Replacing an existing key's value keeps its original position.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.index.get(&key) {
+            Some(&i) => Some(std::mem::replace(&mut self.entries[i].1, value)),
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    pub fn remove<Q: Eq + Hash + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            *self.index.get_mut::<K>(key).unwrap() -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.entries.iter().map(entry_refs as fn(&(K, V)) -> (&K, &V))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    pub fn into_keys(self) -> impl Iterator<Item = K> {
+        self.into_iter().map(|(key, _)| key)
+    }
+
+    pub fn into_values(self) -> impl Iterator<Item = V> {
+        self.into_iter().map(|(_, value)| value)
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> Default for OrderedMap<K, V> {
+    fn default() -> Self {
+        OrderedMap::new()
+    }
+}
+
+// Maps are equal if they have the same entries, regardless of order.
+impl<K: Eq + Hash + Clone, V: PartialEq> PartialEq for OrderedMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: Eq + Hash + Clone, V, Q: Eq + Hash + ?Sized> Index<&Q> for OrderedMap<K, V>
+where
+    K: Borrow<Q>,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("key not found in OrderedMap")
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> Extend<(K, V)> for OrderedMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> FromIterator<(K, V)> for OrderedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = OrderedMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+pub type Iter<'a, K, V> = std::iter::Map<slice::Iter<'a, (K, V)>, fn(&'a (K, V)) -> (&'a K, &'a V)>;
+
+fn entry_refs<K, V>((key, value): &(K, V)) -> (&K, &V) {
+    (key, value)
+}
+
+impl<'a, K: Eq + Hash + Clone, V> IntoIterator for &'a OrderedMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> IntoIterator for OrderedMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = vec::IntoIter<(K, V)>;
+
+    // The derived Trace impl also implements Drop, so the entries can't be moved out directly.
+    fn into_iter(mut self) -> Self::IntoIter {
+        std::mem::take(&mut self.entries).into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OrderedMap;
+
+    #[test]
+    fn ordered_map() {
+        let mut map = OrderedMap::<String, i32>::new();
+        map.insert("c".into(), 1);
+        map.insert("a".into(), 2);
+        map.insert("b".into(), 3);
+        assert_eq!(vec!["c", "a", "b"], map.keys().collect::<Vec<_>>());
+
+        // Replacing keeps the key in place.
+        assert_eq!(Some(2), map.insert("a".into(), 4));
+        let entries = map.iter().map(|(k, v)| (k.as_str(), *v)).collect::<Vec<_>>();
+        assert_eq!(vec![("c", 1), ("a", 4), ("b", 3)], entries);
+
+        // Removal shifts later keys down, and re-inserting appends.
+        assert_eq!(Some(1), map.remove("c"));
+        assert_eq!(None, map.remove("c"));
+        assert_eq!(Some(&3), map.get("b"));
+        map.insert("c".into(), 5);
+        assert_eq!(vec!["a", "b", "c"], map.keys().collect::<Vec<_>>());
+        assert_eq!(5, map["c"]);
+
+        // Equality ignores order.
+        let other: OrderedMap<String, i32> = vec![("c".into(), 5), ("b".into(), 3), ("a".into(), 4)]
+            .into_iter()
+            .collect();
+        assert!(map == other);
+    }
+}
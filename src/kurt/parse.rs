@@ -1,10 +1,17 @@
+use pest::error::Error;
+use pest::error::ErrorVariant;
+use pest::error::LineColLocation;
 use pest::iterators::Pair;
 use pest::Parser;
 
 use crate::kurt::expr::Apply;
 use crate::kurt::expr::Assoc;
 use crate::kurt::expr::Block;
+use crate::kurt::expr::Key;
 use crate::kurt::expr::List;
+use crate::kurt::expr::Param;
+use crate::kurt::expr::Pattern;
+use crate::kurt::ordered_map::OrderedMap;
 use crate::kurt::expr::_bool;
 use crate::kurt::expr::_id;
 use crate::kurt::expr::_loc;
@@ -12,8 +19,11 @@ use crate::kurt::expr::_NIL;
 use crate::kurt::expr::_num;
 use crate::kurt::expr::_q;
 use crate::kurt::ERef;
+use crate::kurt::Exception;
 use crate::kurt::Expr;
 use crate::kurt::Loc;
+use crate::kurt::expr::_list;
+use crate::kurt::expr::_spread;
 use crate::kurt::expr::_str;
 use crate::kurt::expr::_uq;
 
@@ -24,14 +34,77 @@ use super::Kurt;
 struct KurtParser;
 
 impl Kurt {
-    pub fn parse(&self, name: &str, src: &str) -> Expr {
-        let file = KurtParser::parse(Rule::file, src)
-            .expect("unsuccessful parse")
-            .next()
-            .unwrap();
-
-        let expr = file.into_inner().next().unwrap();
-        self.parse_value(name, expr)
+    // Parses a source file into its sequence of top-level forms.
+    pub fn parse(&self, env: &Expr, name: &str, src: &str) -> Result<Vec<Expr>, Exception> {
+        let file = match KurtParser::parse(Rule::file, src) {
+            Ok(mut pairs) => pairs.next().unwrap(),
+            Err(err) => return self.throw_map(env, parse_error(name, src, err)),
+        };
+
+        Ok(file
+            .into_inner()
+            .filter(|expr| expr.as_rule() != Rule::EOI)
+            .map(|expr| self.parse_value(name, expr))
+            .collect())
+    }
+
+    // Parses a native's param spec, in the same form as block params (e.g. "x", "step:1", "vals...").
+    // Specs come from host code, so a bad one is reported rather than treated as a bug.
+    pub fn parse_param(&self, spec: &str) -> Result<Param, String> {
+        match KurtParser::parse(Rule::param_spec, spec) {
+            Ok(mut pairs) => {
+                let param = pairs.next().unwrap().into_inner().next().unwrap();
+                Ok(self.parse_param_pair("native", param))
+            }
+            Err(err) => Err(format!("invalid param spec '{}': {}", spec, err)),
+        }
+    }
+
+    fn parse_param_pair(&self, file: &str, param: Pair<Rule>) -> Param {
+        let mut inner = param.into_inner();
+        Param {
+            pattern: self.parse_pattern(file, inner.next().unwrap()),
+            default: inner.next().map(|default| self.parse_default(file, default)),
+        }
+    }
+
+    fn parse_pattern(&self, file: &str, pattern: Pair<Rule>) -> Pattern {
+        let pattern = pattern.into_inner().next().unwrap();
+        match pattern.as_rule() {
+            Rule::id => Pattern::Id(pattern.as_str().to_string()),
+            Rule::rest => Pattern::Rest(pattern.into_inner().next().unwrap().as_str().to_string()),
+            Rule::list_pattern => Pattern::List(
+                pattern
+                    .into_inner()
+                    .map(|param| self.parse_param_pair(file, param))
+                    .collect(),
+            ),
+            Rule::dict_pattern => Pattern::Dict(
+                pattern
+                    .into_inner()
+                    .map(|entry| {
+                        let rule = entry.as_rule();
+                        let mut inner = entry.into_inner();
+                        let key = inner.next().unwrap().as_str().to_string();
+                        let param = match rule {
+                            // {:key pattern}
+                            Rule::key_nested => self.parse_param_pair(file, inner.next().unwrap()),
+                            // {:key} or {:key:default}
+                            _ => Param {
+                                pattern: Pattern::Id(key.clone()),
+                                default: inner.next().map(|default| self.parse_default(file, default)),
+                            },
+                        };
+                        (key, param)
+                    })
+                    .collect(),
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_default(&self, file: &str, default: Pair<Rule>) -> Expr {
+        self.parse_value(file, default.into_inner().next().unwrap())
     }
 
     fn parse_value(&self, file: &str, expr: Pair<Rule>) -> Expr {
@@ -64,10 +137,7 @@ impl Kurt {
                     .next()
                     .unwrap()
                     .into_inner()
-                    .map(|expr| match expr.as_rule() {
-                        Rule::id => expr.as_str().to_string(),
-                        _ => panic!(),
-                    })
+                    .map(|param| self.parse_param_pair(file, param))
                     .collect();
                 let exprs = rules.map(|val| self.parse_value(file, val)).collect();
                 Expr::EBlock(ERef::new(Block {
@@ -79,6 +149,7 @@ impl Kurt {
                     })),
                     env: _NIL,
                     slf: _NIL,
+                    is_macro: false,
                 }))
             }
 
@@ -132,13 +203,23 @@ impl Kurt {
 
             Rule::non_access => self.parse_value(file, expr.into_inner().next().unwrap()),
 
+            // An apply arg, which may be spread into the call's args (arg...).
+            Rule::arg => {
+                let mut inner = expr.into_inner();
+                let value = self.parse_value(file, inner.next().unwrap());
+                match inner.next() {
+                    Some(_) => _spread(&value),
+                    None => value,
+                }
+            }
+
             Rule::nil => _NIL,
             Rule::number => _num(expr.as_str().parse().unwrap()),
             Rule::boolean => _bool(expr.as_str().parse().unwrap()),
             Rule::string => {
-                // Strip quotes.
+                // Strip quotes and decode escapes.
                 let s = expr.as_str();
-                _str(&s[1..s.len()-1])
+                _str(unescape(&s[1..s.len() - 1]).as_str())
             }
             Rule::id => _id(expr.as_str()),
             Rule::prim => self.parse_value(file, expr.into_inner().next().unwrap()),
@@ -151,3 +232,104 @@ impl Kurt {
         }
     }
 }
+
+// Decodes the escape sequences accepted by the `char` rule.
+// UTF-16 surrogate pairs (\uD83D\uDE00) are combined; lone surrogates become U+FFFD.
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('u') => {
+                let hi = hex4(&mut chars);
+                let code = if (0xD800..0xDC00).contains(&hi) {
+                    // High surrogate; only valid if followed by \u<low surrogate>.
+                    let mut rest = chars.clone();
+                    if rest.next() == Some('\\') && rest.next() == Some('u') {
+                        let lo = hex4(&mut rest);
+                        if (0xDC00..0xE000).contains(&lo) {
+                            chars = rest;
+                            0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00)
+                        } else {
+                            0xFFFD
+                        }
+                    } else {
+                        0xFFFD
+                    }
+                } else {
+                    hi
+                };
+                out.push(std::char::from_u32(code).unwrap_or('\u{FFFD}'));
+            }
+            // \" \\ \/
+            Some(c) => out.push(c),
+            None => (),
+        }
+    }
+    out
+}
+
+// Reads the four hex digits of a \u escape (guaranteed by the grammar).
+fn hex4<I: Iterator<Item = char>>(chars: &mut I) -> u32 {
+    let digits: String = chars.take(4).collect();
+    u32::from_str_radix(&digits, 16).unwrap_or(0xFFFD)
+}
+
+// Converts a pest error into exception fields:
+//   { :message :expected :unexpected :file :line :col :source }
+fn parse_error(file: &str, src: &str, err: Error<Rule>) -> OrderedMap<Key, Expr> {
+    let (line, col) = match err.line_col {
+        LineColLocation::Pos(pos) => pos,
+        LineColLocation::Span(start, _) => start,
+    };
+
+    let rule_names = |rules: &Vec<Rule>| -> Vec<String> {
+        rules.iter().map(|rule| format!("{:?}", rule)).collect()
+    };
+    let (expected, unexpected, message) = match &err.variant {
+        ErrorVariant::ParsingError {
+            positives,
+            negatives,
+        } => {
+            let (pos, neg) = (rule_names(positives), rule_names(negatives));
+            let message = match (pos.is_empty(), neg.is_empty()) {
+                (false, true) => format!("expected {}", pos.join(", ")),
+                (true, false) => format!("unexpected {}", neg.join(", ")),
+                (false, false) => format!(
+                    "unexpected {}; expected {}",
+                    neg.join(", "),
+                    pos.join(", ")
+                ),
+                (true, true) => "unknown parsing error".to_string(),
+            };
+            (pos, neg, message)
+        }
+        ErrorVariant::CustomError { message } => (vec![], vec![], message.clone()),
+    };
+
+    let names = |names: Vec<String>| _list(names.iter().map(|name| _str(name)).collect());
+    let mut map = OrderedMap::<Key, Expr>::new();
+    map.insert(
+        "message".into(),
+        _str(format!("parse error: {} ({}:{}:{})", message, file, line, col).as_str()),
+    );
+    map.insert("expected".into(), names(expected));
+    map.insert("unexpected".into(), names(unexpected));
+    map.insert("file".into(), _str(file));
+    map.insert("line".into(), _num(line as f64));
+    map.insert("col".into(), _num(col as f64));
+    map.insert(
+        "source".into(),
+        _str(src.lines().nth(line - 1).unwrap_or("")),
+    );
+    map
+}
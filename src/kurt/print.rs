@@ -1,8 +1,5 @@
-use super::{Expr, expr::Exprs};
-use std::{
-    collections::HashMap,
-    fmt::{self, Display},
-};
+use super::{Expr, expr::{Exprs, Key, Param, Pattern}, ordered_map::OrderedMap};
+use std::fmt::{self, Display};
 
 impl fmt::Display for Exprs {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -10,12 +7,58 @@ impl fmt::Display for Exprs {
     }
 }
 
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.default {
+            Some(default) => write!(f, "{}:{:#}", self.pattern, default),
+            None => write!(f, "{}", self.pattern),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Id(name) => write!(f, "{}", name),
+            Pattern::Rest(name) => write!(f, "{}...", name),
+            Pattern::List(params) => {
+                write!(f, "[")?;
+                write_vec(f, params)?;
+                write!(f, "]")
+            }
+            Pattern::Dict(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, param)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    match (&param.pattern, &param.default) {
+                        (Pattern::Id(name), Some(default)) if name == key => {
+                            write!(f, ":{}:{:#}", key, default)?
+                        }
+                        (Pattern::Id(name), None) if name == key => write!(f, ":{}", key)?,
+                        _ => write!(f, ":{} {}", key, param)?,
+                    }
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Expr::ENil => write!(f, "nil"),
             Expr::ENum(n) => write!(f, "{}", n),
-            Expr::EStr(n) => write!(f, "{}", n),
+            Expr::EStr(n) => {
+                // The alternate flag ({:#}) selects readable mode: quoted and escaped.
+                if f.alternate() {
+                    write!(f, "\"{}\"", escape(n))
+                } else {
+                    write!(f, "{}", n)
+                }
+            }
             Expr::EBool(n) => write!(f, "{}", n),
             Expr::EId(n) => write!(f, "{}", n),
             Expr::ENative(n) => write!(f, "<native {}>", n),
@@ -66,6 +109,13 @@ impl fmt::Display for Expr {
                 let expr = &*eref.borrow();
                 write!(f, "\\{}", expr)
             }
+
+            Expr::ESpread(eref) => {
+                let expr = &*eref.borrow();
+                write!(f, "{}...", expr)
+            }
+
+            Expr::EObject(obj_ref) => write!(f, "<{}>", obj_ref.borrow().name),
         }
     }
 }
@@ -89,7 +139,8 @@ fn write_pairs(f: &mut fmt::Formatter, m: &Vec<(Expr, Expr)>) -> fmt::Result {
 
     let mut i = 0;
     for (key, expr) in m {
-        write!(f, "{} ", key)?;
+        key.fmt(f)?;
+        f.write_char(' ')?;
         match expr {
             Expr::EDict(_) => {
                 f.write_str("{...}")?;
@@ -109,12 +160,12 @@ fn write_pairs(f: &mut fmt::Formatter, m: &Vec<(Expr, Expr)>) -> fmt::Result {
     Ok(())
 }
 
-fn write_map(f: &mut fmt::Formatter, m: &HashMap<String, Expr>) -> fmt::Result {
+fn write_map(f: &mut fmt::Formatter, m: &OrderedMap<Key, Expr>) -> fmt::Result {
     use std::fmt::Write;
 
     let mut i = 0;
     for (name, expr) in m {
-        write!(f, ":{} ", name)?;
+        write!(f, "{} ", name)?;
         match expr {
             Expr::EDict(_) => { f.write_str("{...}")?; () }
             Expr::EList(_) => { f.write_str("[...]")?; () }
@@ -127,3 +178,22 @@ fn write_map(f: &mut fmt::Formatter, m: &HashMap<String, Expr>) -> fmt::Result {
     }
     Ok(())
 }
+
+// Inverse of the parser's unescape(); produces a valid string literal body.
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c.is_control() => out.push_str(format!("\\u{:04X}", c as u32).as_str()),
+            c => out.push(c),
+        }
+    }
+    out
+}
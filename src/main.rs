@@ -1,6 +1,3 @@
-use std::fs;
-
-use crate::kurt::expr::{_app, _id, _qid};
 use crate::kurt::Kurt;
 
 mod kurt;
@@ -10,14 +7,14 @@ extern crate pest_derive;
 
 fn main() {
     const MAIN: &str = "./src/eden.kurt";
-    let src = fs::read_to_string(MAIN).expect("cannot read file");
 
     let kurt = Kurt::new();
-    kurt.eval_src(&kurt.root, MAIN, src.as_str());
-    kurt.eval(
-        &kurt.root,
-        &_app(vec![_app(vec![_id("World"), _qid("init")])]),
-    );
+    if let Err(err) = kurt
+        .run_file(MAIN)
+        .and_then(|_| kurt.call("World.init", vec![]))
+    {
+        println!("{}", err);
+    }
 
     // App::build()
     //     .insert_resource(Msaa { samples: 4 })